@@ -0,0 +1,362 @@
+use crate::github::{GitHubClient, GitHubError};
+use crate::utils::{percent_decode, percent_encode, percent_encode_path};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Deserialize;
+
+/// Host of `bitbucket:` dependencies without their own `host`; any other host is
+/// treated as Bitbucket Server or Data Center.
+pub const CLOUD_HOST: &str = "https://bitbucket.org";
+const CLOUD_API: &str = "https://api.bitbucket.org";
+/// Environment variable holding a bearer token (an access token or HTTP access token).
+pub const TOKEN_ENV: &str = "BITBUCKET_TOKEN";
+/// Environment variables holding app password credentials, used when there is no token.
+pub const USERNAME_ENV: &str = "BITBUCKET_USERNAME";
+pub const APP_PASSWORD_ENV: &str = "BITBUCKET_APP_PASSWORD";
+const PAGE_SIZE: usize = 100;
+const SERVER_PAGE_SIZE: usize = 1000;
+
+#[derive(Deserialize)]
+struct CloudRepository {
+    mainbranch: Option<Branch>,
+}
+
+#[derive(Deserialize)]
+struct Branch {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerBranch {
+    display_id: String,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    // Cloud 返回 `hash`，Server 返回 `id`
+    #[serde(alias = "hash")]
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct CloudPage {
+    #[serde(default)]
+    values: Vec<CloudEntry>,
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CloudEntry {
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerPage {
+    #[serde(default)]
+    values: Vec<String>,
+    is_last_page: bool,
+    next_page_start: Option<usize>,
+}
+
+fn is_cloud(host: &str) -> bool {
+    host == CLOUD_HOST
+}
+
+/// Base URL API requests for `host` go to; credentials are registered for it.
+pub fn api_base(host: &str) -> &str {
+    if is_cloud(host) {
+        CLOUD_API
+    } else {
+        host
+    }
+}
+
+/// `Authorization` value for app password credentials.
+pub fn basic_auth(username: &str, app_password: &str) -> String {
+    format!("Basic {}", BASE64.encode(format!("{}:{}", username, app_password)))
+}
+
+/// REST API URL of a repository; `owner` is the workspace on Cloud and the project key on Server.
+fn repo_url(host: &str, owner: &str, repo: &str) -> String {
+    if is_cloud(host) {
+        format!(
+            "{}/2.0/repositories/{}/{}",
+            CLOUD_API,
+            percent_encode(owner),
+            percent_encode(repo)
+        )
+    } else {
+        format!(
+            "{}/rest/api/1.0/projects/{}/repos/{}",
+            host,
+            percent_encode(owner),
+            percent_encode(repo)
+        )
+    }
+}
+
+/// Raw download URL of `path` at `git_ref`: `src/{ref}/{path}` on Cloud, `raw/{path}?at=` on Server.
+pub fn raw_file_url(host: &str, owner: &str, repo: &str, git_ref: &str, path: &str) -> String {
+    let base = repo_url(host, owner, repo);
+    if is_cloud(host) {
+        format!("{}/src/{}/{}", base, percent_encode(git_ref), percent_encode_path(path))
+    } else {
+        format!("{}/raw/{}?at={}", base, percent_encode_path(path), percent_encode(git_ref))
+    }
+}
+
+/// Repository path of a Cloud or Server `raw_file_url`; `None` for another repository or ref.
+pub fn file_path(host: &str, owner: &str, repo: &str, git_ref: &str, url: &str) -> Option<String> {
+    let base = repo_url(host, owner, repo);
+    let encoded = if is_cloud(host) {
+        url.strip_prefix(&format!("{}/src/{}/", base, percent_encode(git_ref)))?
+    } else {
+        url.strip_prefix(&format!("{}/raw/", base))?
+            .strip_suffix(&format!("?at={}", percent_encode(git_ref)))?
+    };
+    percent_decode(encoded)
+}
+
+/// Page showing `path` at `commit`: `src/` on Cloud, `browse/` on Server.
+pub fn web_url(host: &str, owner: &str, repo: &str, commit: &str, path: &str) -> String {
+    if is_cloud(host) {
+        format!("{}/{}/{}/src/{}/{}", host, owner, repo, commit, path)
+    } else {
+        format!(
+            "{}/projects/{}/repos/{}/browse/{}?at={}",
+            host, owner, repo, path, commit
+        )
+    }
+}
+
+pub async fn default_branch(
+    client: &GitHubClient,
+    host: &str,
+    owner: &str,
+    repo: &str,
+) -> Result<String, GitHubError> {
+    let what = format!("{}/{}", owner, repo);
+    let base = repo_url(host, owner, repo);
+    if is_cloud(host) {
+        let found: CloudRepository = client.get_json(&base, &what).await?;
+        // 空仓库没有主分支
+        found
+            .mainbranch
+            .map(|branch| branch.name)
+            .ok_or_else(|| GitHubError::NotFound(format!("{} has no default branch", what)))
+    } else {
+        let found: ServerBranch = client
+            .get_json(&format!("{}/default-branch", base), &what)
+            .await?;
+        Ok(found.display_id)
+    }
+}
+
+/// Looks `git_ref` up through the `commit` endpoint on Cloud or `commits` on Server.
+pub async fn resolve_commit(
+    client: &GitHubClient,
+    host: &str,
+    owner: &str,
+    repo: &str,
+    git_ref: &str,
+) -> Result<String, GitHubError> {
+    let endpoint = if is_cloud(host) { "commit" } else { "commits" };
+    let url = format!(
+        "{}/{}/{}",
+        repo_url(host, owner, repo),
+        endpoint,
+        percent_encode(git_ref)
+    );
+    let commit: Commit = client
+        .get_json(&url, &format!("{}/{}@{}", owner, repo, git_ref))
+        .await?;
+    Ok(commit.id)
+}
+
+/// Every file at `commit`. Server lists them in pages until `isLastPage`; Cloud only lists
+/// one directory per request, so its `src` listing is walked directory by directory,
+/// starting at `root` since nothing outside it is selected.
+pub async fn list_tree(
+    client: &GitHubClient,
+    host: &str,
+    owner: &str,
+    repo: &str,
+    commit: &str,
+    root: &str,
+) -> Result<Vec<String>, GitHubError> {
+    let base = repo_url(host, owner, repo);
+    let what = format!("{}/{}@{}", owner, repo, commit);
+    if is_cloud(host) {
+        walk_src(client, &base, commit, root, &what).await
+    } else {
+        list_files(client, &base, commit, &what).await
+    }
+}
+
+/// Server's flat `files` listing of the repository at `base`.
+async fn list_files(
+    client: &GitHubClient,
+    base: &str,
+    commit: &str,
+    what: &str,
+) -> Result<Vec<String>, GitHubError> {
+    let mut paths = Vec::new();
+    let mut start = 0;
+    loop {
+        let url = format!(
+            "{}/files?at={}&limit={}&start={}",
+            base,
+            percent_encode(commit),
+            SERVER_PAGE_SIZE,
+            start
+        );
+        let page: ServerPage = client.get_json(&url, what).await?;
+        paths.extend(page.values);
+        match page.next_page_start {
+            Some(next) if !page.is_last_page => start = next,
+            _ => break,
+        }
+    }
+    Ok(paths)
+}
+
+/// Cloud's `src` listing of the repository at `base`, from `root` down.
+async fn walk_src(
+    client: &GitHubClient,
+    base: &str,
+    commit: &str,
+    root: &str,
+    what: &str,
+) -> Result<Vec<String>, GitHubError> {
+    let mut paths = Vec::new();
+    // Cloud 每次只列出一层目录，逐层展开
+    let mut dirs = vec![match root {
+        "" => String::new(),
+        root => format!("{}/", root),
+    }];
+    while let Some(dir) = dirs.pop() {
+        let mut next = Some(format!(
+            "{}/src/{}/{}?pagelen={}",
+            base,
+            percent_encode(commit),
+            percent_encode_path(&dir),
+            PAGE_SIZE
+        ));
+        while let Some(url) = next {
+            let page: CloudPage = client.get_json(&url, what).await?;
+            for entry in page.values {
+                match entry.kind.as_str() {
+                    "commit_file" => paths.push(entry.path),
+                    "commit_directory" => dirs.push(format!("{}/", entry.path)),
+                    _ => {}
+                }
+            }
+            next = page.next;
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const COMMIT: &str = "e83c5163316f89bfbde7d9ab23ca2e25604af290";
+
+    #[tokio::test]
+    async fn test_server_api() {
+        let api = "/rest/api/1.0/projects/OPS/repos/runbooks";
+        let files = |start: usize| {
+            format!("{}/files?at={}&limit={}&start={}", api, COMMIT, SERVER_PAGE_SIZE, start)
+        };
+        let routes = [
+            (
+                format!("{}/default-branch", api),
+                r#"{"id":"refs/heads/main","displayId":"main","type":"BRANCH","isDefault":true}"#
+                    .to_string(),
+            ),
+            (
+                format!("{}/commits/v2", api),
+                format!(r#"{{"id":"{}","displayId":"e83c516","message":"Release v2"}}"#, COMMIT),
+            ),
+            // 按 nextPageStart 翻页，直到 isLastPage
+            (
+                files(0),
+                r#"{"size":2,"limit":2,"isLastPage":false,"values":["README.md","ops/a.md"],
+                    "start":0,"nextPageStart":2}"#
+                    .to_string(),
+            ),
+            (
+                files(2),
+                r#"{"size":1,"limit":2,"isLastPage":true,"values":["ops/b.md"],"start":2}"#
+                    .to_string(),
+            ),
+        ];
+        let host = testing::serve(&routes).await;
+        let client = GitHubClient::new(None);
+
+        assert_eq!(
+            default_branch(&client, &host, "OPS", "runbooks").await.unwrap(),
+            "main"
+        );
+        assert_eq!(
+            resolve_commit(&client, &host, "OPS", "runbooks", "v2").await.unwrap(),
+            COMMIT
+        );
+        assert_eq!(
+            list_tree(&client, &host, "OPS", "runbooks", COMMIT, "ops").await.unwrap(),
+            vec!["README.md", "ops/a.md", "ops/b.md"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cloud_api() {
+        let api = "/2.0/repositories/team/runbooks";
+        let src = |dir: &str| format!("{}/src/{}/{}?pagelen={}", api, COMMIT, dir, PAGE_SIZE);
+        let routes = [
+            // 每页的 next 链接指向下一页，最后一页没有 next
+            (
+                src("ops/"),
+                format!(
+                    r#"{{"pagelen":100,"page":1,"values":[
+                        {{"path":"ops/a.md","type":"commit_file","size":12}},
+                        {{"path":"ops/sub","type":"commit_directory"}}],
+                        "next":"{{base}}{}&page=2"}}"#,
+                    src("ops/")
+                ),
+            ),
+            (
+                format!("{}&page=2", src("ops/")),
+                r#"{"pagelen":100,"page":2,
+                    "values":[{"path":"ops/b.md","type":"commit_file","size":8}]}"#
+                    .to_string(),
+            ),
+            (
+                src("ops/sub/"),
+                r#"{"pagelen":100,"page":1,
+                    "values":[{"path":"ops/sub/c.md","type":"commit_file","size":3}]}"#
+                    .to_string(),
+            ),
+        ];
+        let base = format!("{}{}", testing::serve(&routes).await, api);
+        let client = GitHubClient::new(None);
+
+        // 只展开依赖所在的目录
+        assert_eq!(
+            walk_src(&client, &base, COMMIT, "ops", "team/runbooks").await.unwrap(),
+            vec!["ops/a.md", "ops/b.md", "ops/sub/c.md"]
+        );
+
+        let recorded = r#"{"full_name":"team/runbooks","mainbranch":{"type":"branch","name":"main"}}"#;
+        let repository: CloudRepository = serde_json::from_str(recorded).unwrap();
+        assert_eq!(repository.mainbranch.unwrap().name, "main");
+        let commit: Commit =
+            serde_json::from_str(&format!(r#"{{"type":"commit","hash":"{}"}}"#, COMMIT)).unwrap();
+        assert_eq!(commit.id, COMMIT);
+    }
+}
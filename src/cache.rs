@@ -0,0 +1,263 @@
+use crate::utils;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// User-level content-addressed download cache shared by every project.
+///
+/// ```text
+/// $CARDO_HOME/
+///   blobs/<aa>/<sha256>      file content, named by its SHA-256
+///   index/<sha256 of url>    "<content sha256> <url>" for immutable (commit-pinned) URLs
+///   used/<sha256>            empty file whose modification time is the blob's last use
+///   git/<sha256 of url>      shallow bare clones of git repositories
+/// ```
+pub struct Cache {
+    root: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub sha256: String,
+    pub size: u64,
+    pub last_used: SystemTime,
+    pub urls: Vec<String>,
+}
+
+impl Cache {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// `$CARDO_HOME`, falling back to `$XDG_CACHE_HOME/cardo` and `~/.cache/cardo`.
+    pub fn from_env() -> Option<Self> {
+        let root = if let Some(home) = std::env::var_os("CARDO_HOME") {
+            PathBuf::from(home)
+        } else if let Some(cache) = std::env::var_os("XDG_CACHE_HOME") {
+            PathBuf::from(cache).join("cardo")
+        } else {
+            let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+            PathBuf::from(home).join(".cache").join("cardo")
+        };
+        Some(Self::new(root))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.root
+            .join("blobs")
+            .join(&sha256[..sha256.len().min(2)])
+            .join(sha256)
+    }
+
+    fn used_path(&self, sha256: &str) -> PathBuf {
+        self.root.join("used").join(sha256)
+    }
+
+    /// Records that a blob was just used, for `gc`. The blob itself is left alone: it may be
+    /// hardlinked into projects, and touching it would change their files too.
+    fn mark_used(&self, sha256: &str) {
+        let path = self.used_path(sha256);
+        let _ = fs::create_dir_all(self.root.join("used"));
+        let _ = fs::File::options()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(SystemTime::now()));
+    }
+
+    fn index_path(&self, url: &str) -> PathBuf {
+        self.root.join("index").join(utils::sha256_hex(url.as_bytes()))
+    }
+
+    /// Content hash previously recorded for an immutable URL.
+    pub fn lookup(&self, url: &str) -> Option<String> {
+        let entry = fs::read_to_string(self.index_path(url)).ok()?;
+        let (sha256, _) = entry.split_once(' ')?;
+        Some(sha256.to_string())
+    }
+
+    /// Reads a blob, discarding it if its content no longer matches its name.
+    pub fn get(&self, sha256: &str) -> Option<Vec<u8>> {
+        let path = self.blob_path(sha256);
+        let content = fs::read(&path).ok()?;
+        if utils::sha256_hex(&content) != sha256 {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        self.mark_used(sha256);
+        Some(content)
+    }
+
+    /// Stores `content`, recording `url` in the index when it points at immutable content.
+    pub fn put(&self, content: &[u8], url: Option<&str>) -> io::Result<String> {
+        let sha256 = utils::sha256_hex(content);
+        let blob = self.blob_path(&sha256);
+        if !blob.exists() {
+            utils::write_atomic(&blob, content)?;
+        }
+        if let Some(url) = url {
+            utils::write_atomic(&self.index_path(url), format!("{} {}", sha256, url).as_bytes())?;
+        }
+        self.mark_used(&sha256);
+        Ok(sha256)
+    }
+
+    /// Hardlinks a cached blob to `dest`, falling back to a copy across filesystems.
+    pub fn install(&self, sha256: &str, dest: &Path) -> io::Result<()> {
+        let blob = self.blob_path(sha256);
+        self.mark_used(sha256);
+        if fs::hard_link(&blob, dest).is_ok() {
+            return Ok(());
+        }
+        fs::copy(&blob, dest).map(|_| ())
+    }
+
+    pub fn list(&self) -> io::Result<Vec<CacheEntry>> {
+        let blobs = self.root.join("blobs");
+        if !blobs.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = Vec::new();
+        for path in utils::list_files(&blobs)? {
+            let sha256 = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            // 跳过其他进程尚未完成的临时文件
+            if sha256.len() != 64 {
+                continue;
+            }
+            let metadata = fs::metadata(&path)?;
+            // 没有使用记录的旧缓存按写入时间计算
+            let last_used = match fs::metadata(self.used_path(&sha256)) {
+                Ok(used) => used.modified()?,
+                Err(_) => metadata.modified()?,
+            };
+            entries.push(CacheEntry {
+                sha256,
+                size: metadata.len(),
+                last_used,
+                urls: Vec::new(),
+            });
+        }
+
+        for (sha256, url) in self.index_entries()? {
+            if let Some(entry) = entries.iter_mut().find(|e| e.sha256 == sha256) {
+                entry.urls.push(url);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Deletes blobs unused for longer than `max_age` and index entries whose blob is gone.
+    /// Returns the number of blobs removed and the bytes freed.
+    pub fn gc(&self, max_age: Duration) -> io::Result<(usize, u64)> {
+        let now = SystemTime::now();
+        let mut removed = 0;
+        let mut freed = 0;
+
+        for entry in self.list()? {
+            let age = now.duration_since(entry.last_used).unwrap_or_default();
+            if age >= max_age {
+                fs::remove_file(self.blob_path(&entry.sha256))?;
+                removed += 1;
+                freed += entry.size;
+            }
+        }
+
+        let used = self.root.join("used");
+        if used.exists() {
+            for path in utils::list_files(&used)? {
+                let sha256 = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                if !self.blob_path(&sha256).exists() {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+
+        let index = self.root.join("index");
+        if index.exists() {
+            for path in utils::list_files(&index)? {
+                let stale = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|e| e.split_once(' ').map(|(sha, _)| sha.to_string()))
+                    .map(|sha| !self.blob_path(&sha).exists())
+                    .unwrap_or(true);
+                if stale {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+
+        Ok((removed, freed))
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        if self.root.exists() {
+            fs::remove_dir_all(&self.root)?;
+        }
+        Ok(())
+    }
+
+    fn index_entries(&self) -> io::Result<Vec<(String, String)>> {
+        let index = self.root.join("index");
+        if !index.exists() {
+            return Ok(Vec::new());
+        }
+
+        Ok(utils::list_files(&index)?
+            .iter()
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .filter_map(|entry| {
+                entry
+                    .split_once(' ')
+                    .map(|(sha, url)| (sha.to_string(), url.trim().to_string()))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_put_get_and_gc() {
+        let root = std::env::temp_dir().join(format!("cardo-cache-{}", std::process::id()));
+        let cache = Cache::new(root.clone());
+        let url = "https://raw.githubusercontent.com/owner/repo/0123abc/a.md";
+
+        let sha256 = cache.put(b"# hello", Some(url)).unwrap();
+        assert_eq!(cache.lookup(url), Some(sha256.clone()));
+        assert_eq!(cache.get(&sha256).unwrap(), b"# hello");
+
+        let entries = cache.list().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].urls, vec![url.to_string()]);
+
+        // 使用时间记在 used/ 下，硬链接到项目中的文件不受影响
+        let installed = root.join("a.md");
+        cache.install(&sha256, &installed).unwrap();
+        let old = SystemTime::now() - Duration::from_secs(7200);
+        fs::File::options()
+            .append(true)
+            .open(&installed)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        assert_eq!(cache.get(&sha256).unwrap(), b"# hello");
+        assert_eq!(fs::metadata(&installed).unwrap().modified().unwrap(), old);
+        assert_eq!(cache.gc(Duration::from_secs(3600)).unwrap().0, 0);
+
+        assert_eq!(cache.gc(Duration::ZERO).unwrap().0, 1);
+        assert_eq!(cache.lookup(url), None);
+        assert!(!cache.used_path(&sha256).exists());
+
+        cache.clear().unwrap();
+        assert!(!root.exists());
+    }
+}
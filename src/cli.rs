@@ -5,6 +5,10 @@ use clap::{Parser, Subcommand};
 #[command(about = "A CLI tool for managing Markdown file dependencies", long_about = None)]
 #[command(version)]
 pub struct Cli {
+    /// Fail instead of waiting when another cardo process is working on the project
+    #[arg(long, global = true)]
+    pub no_wait: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -19,18 +23,100 @@ pub enum Commands {
     },
     /// Fetch all dependencies from markdown.toml
     Fetch {
-        /// Force re-download even if files exist
+        /// Force re-download, and replace files at output paths that cardo did not write
         #[arg(short, long)]
         force: bool,
+        /// Number of parallel downloads (default: [fetch] jobs or 8)
+        #[arg(short, long, value_name = "N")]
+        jobs: Option<usize>,
+        /// Use only markdown.lock and the download cache; never access the network
+        #[arg(long)]
+        offline: bool,
+        /// Fail if markdown.lock is missing or out of date
+        #[arg(long)]
+        locked: bool,
+        /// Equivalent to --offline --locked
+        #[arg(long)]
+        frozen: bool,
+        /// Keep files from earlier fetches that no dependency writes any more
+        #[arg(long)]
+        no_prune: bool,
     },
-    /// Update dependencies (same as fetch for now)
+    /// Re-resolve dependency versions and rewrite markdown.lock
     Update {
-        /// Force re-download even if files exist
+        /// Only update these dependencies (default: all)
+        #[arg(value_name = "NAME")]
+        names: Vec<String>,
+        /// Pin the single named dependency to this tag, branch or commit
+        #[arg(long, value_name = "REV", requires = "names")]
+        precise: Option<String>,
+        /// Force re-download, and replace files at output paths that cardo did not write
         #[arg(short, long)]
         force: bool,
+        /// Number of parallel downloads (default: [fetch] jobs or 8)
+        #[arg(short, long, value_name = "N")]
+        jobs: Option<usize>,
+        /// Keep files from earlier fetches that no dependency writes any more
+        #[arg(long)]
+        no_prune: bool,
+        /// Apply the dependencies that succeeded even if others fail
+        #[arg(long)]
+        keep_going: bool,
+    },
+    /// Add a dependency to markdown.toml
+    Add {
+        /// Dependency name
+        name: String,
+        /// Source, e.g. github:owner/repo/path.md or https://example.com/doc.md
+        spec: String,
+        /// Pin to a tag
+        #[arg(long, conflicts_with_all = ["branch", "rev"])]
+        tag: Option<String>,
+        /// Track a branch
+        #[arg(long, conflicts_with = "rev")]
+        branch: Option<String>,
+        /// Pin to a commit
+        #[arg(long)]
+        rev: Option<String>,
+    },
+    /// Remove a dependency from markdown.toml and delete its fetched files
+    Remove {
+        /// Dependency name
+        name: String,
     },
     /// List all dependencies
     List,
-    /// Clean the markdowns directory
-    Clean,
+    /// Remove the files cardo wrote to the output directory
+    Clean {
+        /// Show what would be removed without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Empty the whole output directory, including files cardo did not write
+        #[arg(long)]
+        all: bool,
+    },
+    /// Print the content hash of a fetched dependency for use in markdown.toml
+    Hash {
+        /// Dependency name
+        name: String,
+    },
+    /// Manage the shared download cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// List cached files
+    List,
+    /// Remove cached files that have not been used recently
+    Gc {
+        /// Remove files unused for this many days
+        #[arg(long, value_name = "N", default_value_t = 30)]
+        days: u64,
+    },
+    /// Remove every cached file
+    Clear,
 }
@@ -4,21 +4,53 @@ use std::fs;
 use std::path::Path;
 use thiserror::Error;
 
-use crate::dependency::{DependencyError, DependencySource};
+use crate::dependency::{Dependency, DependencyError};
+use crate::layout::{Layout, LayoutError};
+
+pub const DEFAULT_OUT_DIR: &str = "markdowns";
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MarkdownConfig {
     pub package: Package,
     #[serde(default)]
     pub dependencies: HashMap<String, toml::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetch: Option<FetchConfig>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FetchConfig {
+    /// Maximum number of concurrent downloads.
+    pub jobs: Option<usize>,
+    /// Largest asset, in bytes, fetched for dependencies with `assets = true`.
+    pub max_asset_size: Option<u64>,
+    /// Environment variables holding the API token for a GitLab, Gitea or Bitbucket host,
+    /// keyed by the host's base URL.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tokens: HashMap<String, String>,
+}
+
+/// The part of another project's markdown.toml that cardo reads for transitive dependencies.
+#[derive(Debug, Deserialize)]
+struct UpstreamManifest {
+    #[serde(default)]
+    dependencies: HashMap<String, toml::Value>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct Package {
     pub name: String,
     pub version: String,
     #[serde(default)]
     pub description: Option<String>,
+    /// Directory fetched files are written to, relative to markdown.toml.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_dir: Option<String>,
+    /// `by-repo`, `by-name`, `flat` or a path template.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
 }
 
 #[derive(Debug, Error)]
@@ -31,6 +63,10 @@ pub enum ConfigError {
     DependencyError(#[from] DependencyError),
     #[error("Config file not found: {0}")]
     NotFound(String),
+    #[error("Failed to edit TOML: {0}")]
+    EditError(#[from] toml_edit::TomlError),
+    #[error("Invalid layout: {0}")]
+    LayoutError(#[from] LayoutError),
 }
 
 impl MarkdownConfig {
@@ -45,17 +81,28 @@ impl MarkdownConfig {
         Ok(config)
     }
 
-    pub fn parse_dependencies(&self) -> Result<HashMap<String, DependencySource>, ConfigError> {
+    pub fn parse_dependencies(&self) -> Result<HashMap<String, Dependency>, ConfigError> {
         let mut deps = HashMap::new();
 
         for (name, value) in &self.dependencies {
-            let source = DependencySource::parse(value)?;
-            deps.insert(name.clone(), source);
+            let dependency = Dependency::parse(value)?;
+            deps.insert(name.clone(), dependency);
         }
 
         Ok(deps)
     }
 
+    pub fn out_dir(&self) -> &str {
+        self.package.out_dir.as_deref().unwrap_or(DEFAULT_OUT_DIR)
+    }
+
+    pub fn layout(&self) -> Result<Layout, ConfigError> {
+        match &self.package.layout {
+            Some(layout) => Ok(Layout::parse(layout)?),
+            None => Ok(Layout::default()),
+        }
+    }
+
     pub fn to_toml_string(&self) -> Result<String, toml::ser::Error> {
         toml::to_string_pretty(self)
     }
@@ -66,12 +113,71 @@ impl MarkdownConfig {
                 name: name.to_string(),
                 version: "0.1.0".to_string(),
                 description: Some("A collection of Markdown documentation files".to_string()),
+                out_dir: None,
+                layout: None,
             },
             dependencies: HashMap::new(),
+            fetch: None,
         }
     }
 }
 
+/// Parses the dependencies declared by a manifest fetched from a dependency's repository.
+pub fn parse_upstream_dependencies(
+    content: &str,
+) -> Result<HashMap<String, Dependency>, ConfigError> {
+    let manifest: UpstreamManifest = toml::from_str(content)?;
+    let mut deps = HashMap::new();
+    for (name, value) in &manifest.dependencies {
+        deps.insert(name.clone(), Dependency::parse(value)?);
+    }
+    Ok(deps)
+}
+
+/// Inserts or replaces `name` under `[dependencies]`, keeping the rest of the file's
+/// formatting and comments intact. Returns `true` if an existing entry was replaced.
+pub fn add_dependency<P: AsRef<Path>>(
+    path: P,
+    name: &str,
+    value: toml_edit::Value,
+) -> Result<bool, ConfigError> {
+    let path = path.as_ref();
+    let mut doc = fs::read_to_string(path)?.parse::<toml_edit::DocumentMut>()?;
+
+    let dependencies = doc
+        .entry("dependencies")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .ok_or_else(|| {
+            ConfigError::DependencyError(DependencyError::InvalidFormat(
+                "[dependencies] must be a table".to_string(),
+            ))
+        })?;
+    let replaced = dependencies
+        .insert(name, toml_edit::Item::Value(value))
+        .is_some();
+
+    crate::utils::write_atomic(path, doc.to_string().as_bytes())?;
+    Ok(replaced)
+}
+
+/// Removes `name` from `[dependencies]`, returning `false` if it was not declared.
+pub fn remove_dependency<P: AsRef<Path>>(path: P, name: &str) -> Result<bool, ConfigError> {
+    let path = path.as_ref();
+    let mut doc = fs::read_to_string(path)?.parse::<toml_edit::DocumentMut>()?;
+
+    let removed = doc
+        .get_mut("dependencies")
+        .and_then(|deps| deps.as_table_like_mut())
+        .and_then(|deps| deps.remove(name))
+        .is_some();
+
+    if removed {
+        crate::utils::write_atomic(path, doc.to_string().as_bytes())?;
+    }
+    Ok(removed)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,5 +196,79 @@ example = "github:owner/repo/README.md"
         let config: MarkdownConfig = toml::from_str(toml_str).unwrap();
         assert_eq!(config.package.name, "test-project");
         assert_eq!(config.dependencies.len(), 1);
+        assert!(config.fetch.is_none());
+        assert_eq!(config.out_dir(), DEFAULT_OUT_DIR);
+        assert_eq!(config.layout().unwrap(), Layout::ByRepo);
+    }
+
+    #[test]
+    fn test_parse_output_settings() {
+        let toml_str = r#"
+[package]
+name = "test-project"
+version = "0.1.0"
+out-dir = "docs/vendor"
+layout = "{name}/{file}"
+"#;
+
+        let config: MarkdownConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.out_dir(), "docs/vendor");
+        assert_eq!(
+            config.layout().unwrap(),
+            Layout::Template("{name}/{file}".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_fetch_config() {
+        let toml_str = r#"
+[package]
+name = "test-project"
+version = "0.1.0"
+
+[fetch]
+jobs = 16
+max-asset-size = 1048576
+
+[fetch.tokens]
+"https://git.example.com" = "EXAMPLE_TOKEN"
+"#;
+
+        let config: MarkdownConfig = toml::from_str(toml_str).unwrap();
+        let fetch = config.fetch.unwrap();
+        assert_eq!(fetch.jobs, Some(16));
+        assert_eq!(fetch.max_asset_size, Some(1048576));
+        assert_eq!(fetch.tokens["https://git.example.com"], "EXAMPLE_TOKEN");
+    }
+
+    #[test]
+    fn test_add_and_remove_dependency_preserves_comments() {
+        let path = std::env::temp_dir().join(format!("cardo-config-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            r#"[package]
+name = "test-project" # keep me
+version = "0.1.0"
+
+[dependencies]
+example = "github:owner/repo/README.md" # trailing
+other = "https://example.com/other.md"
+"#,
+        )
+        .unwrap();
+
+        let mut table = toml_edit::InlineTable::new();
+        table.insert("git", "github:owner/repo/docs/api.md".into());
+        table.insert("tag", "v1".into());
+        assert!(!add_dependency(&path, "api", table.into()).unwrap());
+        assert!(remove_dependency(&path, "other").unwrap());
+        assert!(!remove_dependency(&path, "missing").unwrap());
+
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(content.contains("# keep me"));
+        assert!(content.contains(r#"example = "github:owner/repo/README.md" # trailing"#));
+        assert!(content.contains(r#"api = { git = "github:owner/repo/docs/api.md", tag = "v1" }"#));
+        assert!(!content.contains("other"));
     }
 }
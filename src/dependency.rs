@@ -1,5 +1,51 @@
+use crate::bitbucket;
+use crate::gist;
+use crate::git;
+use crate::gitea;
+use crate::gitlab;
+use crate::utils;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use thiserror::Error;
 
+const GLOB_CHARS: &[char] = &['*', '?', '[', '{'];
+/// Schemes of sources that accept a `host` for self-hosted instances.
+const HOSTED_SCHEMES: &[&str] = &["gitlab:", "gitea:", "forgejo:", "bitbucket:"];
+/// Link depth used by `follow-links = true`.
+pub const DEFAULT_LINK_DEPTH: usize = 3;
+
+/// A dependency as declared in markdown.toml: where it comes from and how to verify it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub source: DependencySource,
+    /// Expected SHA-256 of the content in hex, from `sha256` or an SRI `integrity` value.
+    pub sha256: Option<String>,
+    /// How relative links in fetched Markdown are rewritten.
+    pub links: LinkMode,
+    /// Output path overriding the layout: the file for single-file dependencies,
+    /// the directory for directories and globs.
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    /// Point links at fetched copies, and at the upstream file pinned to the commit otherwise.
+    #[default]
+    Local,
+    /// Point every relative link at the upstream file pinned to the commit.
+    Absolute,
+    /// Leave links as written upstream.
+    Keep,
+}
+
+impl LinkMode {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DependencySource {
     GitHub {
@@ -7,8 +53,64 @@ pub enum DependencySource {
         repo: String,
         path: String,
         version: Option<Version>,
+        /// How many levels of relative Markdown links to fetch along with the files.
+        follow_links: Option<usize>,
+        /// Also fetch images and other files the Markdown links to by relative path.
+        assets: bool,
+    },
+    /// A project on gitlab.com or a self-managed GitLab at `host`, read through its REST API.
+    /// `project` is the full path including nested groups.
+    GitLab {
+        host: String,
+        project: String,
+        path: String,
+        version: Option<Version>,
+        follow_links: Option<usize>,
+        assets: bool,
+    },
+    /// A repository on Gitea, Forgejo or Codeberg at `host`, read through the Gitea API.
+    Gitea {
+        host: String,
+        owner: String,
+        repo: String,
+        path: String,
+        version: Option<Version>,
+        follow_links: Option<usize>,
+        assets: bool,
+    },
+    /// A repository on Bitbucket Cloud, or on Bitbucket Server or Data Center at `host`.
+    /// `owner` is the workspace on Cloud and the project key on Server.
+    Bitbucket {
+        host: String,
+        owner: String,
+        repo: String,
+        path: String,
+        version: Option<Version>,
+        follow_links: Option<usize>,
+        assets: bool,
+    },
+    /// A file of a GitHub gist, or a glob over its files; `version` is always a revision.
+    Gist {
+        id: String,
+        file: String,
+        version: Option<Version>,
+        follow_links: Option<usize>,
+        assets: bool,
+    },
+    /// Any other git repository, by clone URL (`https://`, `ssh://`, `file://`, ...).
+    Git {
+        url: String,
+        path: String,
+        version: Option<Version>,
+        follow_links: Option<usize>,
+        assets: bool,
     },
     Url(String),
+    /// Local file or directory, relative to the directory containing markdown.toml.
+    Local {
+        path: String,
+        symlink: bool,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -24,10 +126,78 @@ pub enum DependencyError {
     InvalidFormat(String),
     #[error("Invalid GitHub URL: {0}")]
     InvalidGitHubUrl(String),
+    #[error("Invalid GitLab path: {0}")]
+    InvalidGitLabPath(String),
     #[error("Missing required field: {0}")]
     MissingField(String),
 }
 
+impl Dependency {
+    pub fn parse(value: &toml::Value) -> Result<Self, DependencyError> {
+        let source = DependencySource::parse(value)?;
+        let mut dependency = Self::new(source);
+        if let Some(table) = value.as_table() {
+            dependency.sha256 = Self::parse_integrity(table)?;
+            dependency.links = Self::parse_links(table)?;
+            dependency.to = match table.get("to") {
+                None => None,
+                Some(toml::Value::String(to)) if !to.trim_matches('/').is_empty() => {
+                    Some(to.clone())
+                }
+                Some(_) => {
+                    return Err(DependencyError::InvalidFormat(
+                        "to must be a non-empty path".to_string(),
+                    ))
+                }
+            };
+        }
+        Ok(dependency)
+    }
+
+    pub fn new(source: DependencySource) -> Self {
+        Self {
+            source,
+            sha256: None,
+            links: LinkMode::default(),
+            to: None,
+        }
+    }
+
+    fn parse_links(table: &toml::value::Table) -> Result<LinkMode, DependencyError> {
+        match table.get("links").map(|v| v.as_str()) {
+            None => Ok(LinkMode::default()),
+            Some(Some("local")) => Ok(LinkMode::Local),
+            Some(Some("absolute")) => Ok(LinkMode::Absolute),
+            Some(Some("keep")) => Ok(LinkMode::Keep),
+            Some(_) => Err(DependencyError::InvalidFormat(
+                "links must be \"local\", \"absolute\" or \"keep\"".to_string(),
+            )),
+        }
+    }
+
+    fn parse_integrity(table: &toml::value::Table) -> Result<Option<String>, DependencyError> {
+        if let Some(value) = table.get("sha256") {
+            let hex = value.as_str().unwrap_or_default().to_ascii_lowercase();
+            if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(DependencyError::InvalidFormat(
+                    "sha256 must be a 64-character hex digest".to_string(),
+                ));
+            }
+            Ok(Some(hex))
+        } else if let Some(value) = table.get("integrity") {
+            let sri = value.as_str().unwrap_or_default();
+            utils::hex_from_sri(sri).map(Some).ok_or_else(|| {
+                DependencyError::InvalidFormat(format!(
+                    "integrity must be a sha256-<base64> value, got: {}",
+                    sri
+                ))
+            })
+        } else {
+            Ok(None)
+        }
+    }
+}
+
 impl DependencySource {
     pub fn parse(value: &toml::Value) -> Result<Self, DependencyError> {
         match value {
@@ -35,6 +205,10 @@ impl DependencySource {
                 // 尝试解析 github: 格式
                 if let Some(github_str) = s.strip_prefix("github:") {
                     Self::parse_github_simple(github_str)
+                } else if let Some(source) = Self::parse_hosted_simple(s) {
+                    source
+                } else if let Some(gist_str) = s.strip_prefix("gist:") {
+                    Self::parse_gist_simple(gist_str)
                 }
                 // 尝试解析 URL 格式
                 else if s.starts_with("http://") || s.starts_with("https://") {
@@ -47,28 +221,196 @@ impl DependencySource {
                 }
             }
             toml::Value::Table(table) => {
+                for key in ["follow-links", "assets"] {
+                    if table.contains_key(key) && !table.contains_key("git") && !table.contains_key("gist") {
+                        return Err(DependencyError::InvalidFormat(format!(
+                            "{} is only supported for git and gist dependencies",
+                            key
+                        )));
+                    }
+                }
+                let git_str = table.get("git").and_then(|v| v.as_str()).unwrap_or_default();
+                if table.contains_key("host") && !HOSTED_SCHEMES.iter().any(|s| git_str.starts_with(s)) {
+                    return Err(DependencyError::InvalidFormat(
+                        "host is only supported for gitlab:, gitea:, forgejo: and bitbucket: dependencies"
+                            .to_string(),
+                    ));
+                }
+
+                // Gist：{ gist = "<id>", file = "x.md", rev = "<revision sha>" }
+                if let Some(gist_value) = table.get("gist") {
+                    let (Some(id), Some(file)) = (
+                        gist_value.as_str(),
+                        table.get("file").and_then(|v| v.as_str()),
+                    ) else {
+                        return Err(DependencyError::InvalidFormat(
+                            "gist dependencies need gist and file strings".to_string(),
+                        ));
+                    };
+                    if table.contains_key("tag") || table.contains_key("branch") {
+                        return Err(DependencyError::InvalidFormat(
+                            "gists have no tags or branches; pin a revision with rev".to_string(),
+                        ));
+                    }
+                    let mut source = Self::parse_gist_simple(&format!("{}/{}", id, file))?;
+                    if let DependencySource::Gist {
+                        version,
+                        follow_links,
+                        assets,
+                        ..
+                    } = &mut source
+                    {
+                        *version = Self::parse_version(table)?;
+                        *follow_links = Self::parse_follow_links(table)?;
+                        *assets = Self::parse_assets(table)?;
+                    }
+                    return Ok(source);
+                }
+
+                // 本地路径：{ path = "../shared-docs/guide.md", symlink = true }
+                if let Some(path_value) = table.get("path").filter(|_| !table.contains_key("git")) {
+                    let path = path_value.as_str().ok_or_else(|| {
+                        DependencyError::InvalidFormat("path field must be a string".to_string())
+                    })?;
+                    let symlink = table
+                        .get("symlink")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false);
+                    return Ok(DependencySource::Local {
+                        path: path.to_string(),
+                        symlink,
+                    });
+                }
+
+                // URL 表格式：{ url = "https://...", sha256 = "..." }
+                if let Some(url_value) = table.get("url") {
+                    return match url_value.as_str() {
+                        Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+                            Ok(DependencySource::Url(url.to_string()))
+                        }
+                        _ => Err(DependencyError::InvalidFormat(
+                            "url field must be an http(s) URL".to_string(),
+                        )),
+                    };
+                }
+
                 // 解析完整格式：{ git = "github:...", tag/branch/rev = "..." }
                 if let Some(git_value) = table.get("git") {
                     if let Some(git_str) = git_value.as_str() {
+                        let version = Self::parse_version(table)?;
+                        let follow_links = Self::parse_follow_links(table)?;
+                        let assets = Self::parse_assets(table)?;
+                        let glob = table.get("glob").and_then(|v| v.as_str());
+
+                        // 其他 git 仓库：{ git = "https://host/org/repo.git", path = "docs/api.md" }
+                        if Self::is_git_url(git_str) {
+                            Self::check_not_option("git URLs", git_str)?;
+                            let dir = match table.get("path") {
+                                None => None,
+                                Some(toml::Value::String(dir)) => Some(dir.trim_start_matches('/')),
+                                Some(_) => {
+                                    return Err(DependencyError::InvalidFormat(
+                                        "path field must be a string".to_string(),
+                                    ))
+                                }
+                            };
+                            let path = match (dir, glob) {
+                                (Some(dir), Some(glob)) if !dir.trim_end_matches('/').is_empty() => {
+                                    format!("{}/{}", dir.trim_end_matches('/'), glob)
+                                }
+                                (_, Some(glob)) => glob.to_string(),
+                                (Some(dir), None) => dir.to_string(),
+                                (None, None) => {
+                                    return Err(DependencyError::MissingField(
+                                        "path or glob".to_string(),
+                                    ))
+                                }
+                            };
+                            Self::tree_matcher(&path)?;
+                            return Ok(DependencySource::Git {
+                                url: git_str.to_string(),
+                                path,
+                                version,
+                                follow_links,
+                                assets,
+                            });
+                        }
+                        if table.contains_key("path") {
+                            return Err(DependencyError::InvalidFormat(
+                                "path is only used with git URLs; put the path in the git spec"
+                                    .to_string(),
+                            ));
+                        }
+
+                        // { git = "gitlab:group/sub/project/-/docs/api.md", host = "https://gitlab.example.com" }
+                        if let Some(gitlab_str) = git_str.strip_prefix("gitlab:") {
+                            let (project, path) = match glob {
+                                // glob 相对于 `/-/` 之后的目录（可以只写项目路径）
+                                Some(glob) => match gitlab_str.split_once("/-/") {
+                                    Some((project, dir)) if !dir.trim_matches('/').is_empty() => {
+                                        (project, format!("{}/{}", dir.trim_matches('/'), glob))
+                                    }
+                                    Some((project, _)) => (project, glob.to_string()),
+                                    None => (gitlab_str.trim_end_matches('/'), glob.to_string()),
+                                },
+                                None => {
+                                    let (project, path) = Self::parse_gitlab_path(gitlab_str)?;
+                                    (project, path.to_string())
+                                }
+                            };
+                            Self::tree_matcher(&path)?;
+                            return Ok(DependencySource::GitLab {
+                                host: Self::parse_host(table, gitlab::DEFAULT_HOST)?,
+                                project: project.to_string(),
+                                path,
+                                version,
+                                follow_links,
+                                assets,
+                            });
+                        }
+
+                        // { git = "forgejo:owner/repo/docs/api.md", host = "https://forgejo.example.com" }
+                        if let Some((gitea_str, default_host)) = Self::strip_gitea_scheme(git_str) {
+                            let (owner, repo, path) = Self::parse_repo_path(gitea_str, glob)?;
+                            Self::tree_matcher(&path)?;
+                            return Ok(DependencySource::Gitea {
+                                host: Self::parse_host(table, default_host)?,
+                                owner,
+                                repo,
+                                path,
+                                version,
+                                follow_links,
+                                assets,
+                            });
+                        }
+
+                        // { git = "bitbucket:PROJ/repo/docs/runbook.md", host = "https://bitbucket.example.com" }
+                        if let Some(bitbucket_str) = git_str.strip_prefix("bitbucket:") {
+                            let (owner, repo, path) = Self::parse_repo_path(bitbucket_str, glob)?;
+                            Self::tree_matcher(&path)?;
+                            return Ok(DependencySource::Bitbucket {
+                                host: Self::parse_host(table, bitbucket::CLOUD_HOST)?,
+                                owner,
+                                repo,
+                                path,
+                                version,
+                                follow_links,
+                                assets,
+                            });
+                        }
+
                         // 去除 "github:" 前缀（如果存在）
                         let github_path = git_str.strip_prefix("github:").unwrap_or(git_str);
-                        let (owner, repo, path) = Self::parse_github_path(github_path)?;
-                        
-                        let version = if let Some(tag) = table.get("tag").and_then(|v| v.as_str()) {
-                            Some(Version::Tag(tag.to_string()))
-                        } else if let Some(branch) = table.get("branch").and_then(|v| v.as_str()) {
-                            Some(Version::Branch(branch.to_string()))
-                        } else if let Some(rev) = table.get("rev").and_then(|v| v.as_str()) {
-                            Some(Version::Commit(rev.to_string()))
-                        } else {
-                            None
-                        };
+                        let (owner, repo, path) = Self::parse_repo_path(github_path, glob)?;
+                        Self::tree_matcher(&path)?;
 
                         Ok(DependencySource::GitHub {
                             owner,
                             repo,
                             path,
                             version,
+                            follow_links,
+                            assets,
                         })
                     } else {
                         Err(DependencyError::InvalidFormat(
@@ -76,7 +418,7 @@ impl DependencySource {
                         ))
                     }
                 } else {
-                    Err(DependencyError::MissingField("git".to_string()))
+                    Err(DependencyError::MissingField("git, gist, url or path".to_string()))
                 }
             }
             _ => Err(DependencyError::InvalidFormat(
@@ -85,16 +427,529 @@ impl DependencySource {
         }
     }
 
+    fn parse_version(table: &toml::value::Table) -> Result<Option<Version>, DependencyError> {
+        let Some((key, value)) = ["tag", "branch", "rev"]
+            .into_iter()
+            .find_map(|key| table.get(key).and_then(|v| v.as_str()).map(|v| (key, v)))
+        else {
+            return Ok(None);
+        };
+        Self::check_not_option(key, value)?;
+        let value = value.to_string();
+        Ok(Some(match key {
+            "tag" => Version::Tag(value),
+            "branch" => Version::Branch(value),
+            _ => Version::Commit(value),
+        }))
+    }
+
+    /// Refs and clone URLs are passed to `git`; one starting with `-` would be read as an option.
+    fn check_not_option(what: &str, value: &str) -> Result<(), DependencyError> {
+        if value.starts_with('-') {
+            return Err(DependencyError::InvalidFormat(format!(
+                "{} must not start with '-': {}",
+                what, value
+            )));
+        }
+        Ok(())
+    }
+
+    fn parse_follow_links(table: &toml::value::Table) -> Result<Option<usize>, DependencyError> {
+        match table.get("follow-links") {
+            None | Some(toml::Value::Boolean(false)) => Ok(None),
+            Some(toml::Value::Boolean(true)) => Ok(Some(DEFAULT_LINK_DEPTH)),
+            Some(toml::Value::Integer(depth)) if *depth >= 0 => {
+                Ok(Some(*depth as usize).filter(|d| *d > 0))
+            }
+            Some(_) => Err(DependencyError::InvalidFormat(
+                "follow-links must be true, false or a depth".to_string(),
+            )),
+        }
+    }
+
+    fn parse_assets(table: &toml::value::Table) -> Result<bool, DependencyError> {
+        match table.get("assets") {
+            None => Ok(false),
+            Some(toml::Value::Boolean(assets)) => Ok(*assets),
+            Some(_) => Err(DependencyError::InvalidFormat(
+                "assets must be true or false".to_string(),
+            )),
+        }
+    }
+
+    /// Base URL of a self-hosted instance from `host`, or `default`.
+    fn parse_host(table: &toml::value::Table, default: &str) -> Result<String, DependencyError> {
+        match table.get("host") {
+            None => Ok(default.to_string()),
+            Some(toml::Value::String(host))
+                if host.starts_with("http://") || host.starts_with("https://") =>
+            {
+                Ok(host.trim_end_matches('/').to_string())
+            }
+            Some(_) => Err(DependencyError::InvalidFormat(
+                "host must be an http(s) base URL".to_string(),
+            )),
+        }
+    }
+
+    /// `owner/repo/path`, where a `glob` is relative to the directory given (which may
+    /// be just `owner/repo`).
+    fn parse_repo_path(
+        s: &str,
+        glob: Option<&str>,
+    ) -> Result<(String, String, String), DependencyError> {
+        match glob {
+            Some(glob) => {
+                let base = format!("{}/", s.trim_end_matches('/'));
+                let (owner, repo, dir) = Self::parse_github_path(&base)?;
+                Ok((owner, repo, format!("{}{}", dir, glob)))
+            }
+            None => Self::parse_github_path(s),
+        }
+    }
+
+    /// Splits off `gitea:` or `forgejo:`, returning the rest and the scheme's default host.
+    fn strip_gitea_scheme(s: &str) -> Option<(&str, &'static str)> {
+        if let Some(rest) = s.strip_prefix("gitea:") {
+            Some((rest, gitea::DEFAULT_HOST))
+        } else {
+            s.strip_prefix("forgejo:").map(|rest| (rest, gitea::CODEBERG_HOST))
+        }
+    }
+
+    /// Clone URLs, as opposed to the `owner/repo/path` shorthand for GitHub.
+    fn is_git_url(s: &str) -> bool {
+        s.contains("://") || s.starts_with("git@")
+    }
+
+    /// Parses a lock file source id, the inverse of `Display`.
+    pub fn from_id(id: &str) -> Result<Self, DependencyError> {
+        let source = Self::parse_id(id)?;
+        if let DependencySource::Git { url, .. } = &source {
+            Self::check_not_option("git URLs", url)?;
+        }
+        if let Some(git_ref) = source.git_ref() {
+            Self::check_not_option("tag, branch and rev", git_ref)?;
+        }
+        Ok(source)
+    }
+
+    fn parse_id(id: &str) -> Result<Self, DependencyError> {
+        if let Some(path) = id.strip_prefix("path:") {
+            return Ok(DependencySource::Local {
+                path: path.to_string(),
+                symlink: false,
+            });
+        }
+        // git+<url>#<path>?<query>
+        if let Some(git_str) = id.strip_prefix("git+") {
+            let (url, rest) = git_str
+                .split_once('#')
+                .ok_or_else(|| DependencyError::InvalidFormat(format!("Invalid source: {}", id)))?;
+            let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+            let mut source = DependencySource::Git {
+                url: url.to_string(),
+                path: path.to_string(),
+                version: None,
+                follow_links: None,
+                assets: false,
+            };
+            source.apply_query(query);
+            source.unescape_path();
+            return Ok(source);
+        }
+        // gitlab:<project>/-/<path>?host=<url>&<query>，gitea:/forgejo:/bitbucket: 同理
+        let (spec, query) = id.split_once('?').unwrap_or((id, ""));
+        if let Some(source) = Self::parse_hosted_simple(spec) {
+            let mut source = source?;
+            if let DependencySource::GitLab { host, .. }
+            | DependencySource::Gitea { host, .. }
+            | DependencySource::Bitbucket { host, .. } = &mut source
+            {
+                if let Some(custom) = query.split('&').find_map(|pair| pair.strip_prefix("host=")) {
+                    *host = custom.to_string();
+                }
+            }
+            source.apply_query(query);
+            source.unescape_path();
+            return Ok(source);
+        }
+        // gist:<id>/<file>?rev=<sha>
+        if let Some(gist_str) = spec.strip_prefix("gist:") {
+            let mut source = Self::parse_gist_simple(gist_str)?;
+            source.apply_query(query);
+            source.unescape_path();
+            return Ok(source);
+        }
+        let Some(github_str) = id.strip_prefix("github:") else {
+            return Self::parse(&toml::Value::String(id.to_string()));
+        };
+
+        let (spec, query) = github_str.split_once('?').unwrap_or((github_str, ""));
+        let mut source = Self::parse_github_simple(spec)?;
+        source.apply_query(query);
+        source.unescape_path();
+        Ok(source)
+    }
+
+    /// Restores the path a source id carries, which `identity` escaped.
+    fn unescape_path(&mut self) {
+        match self {
+            DependencySource::GitHub { path, .. }
+            | DependencySource::GitLab { path, .. }
+            | DependencySource::Gitea { path, .. }
+            | DependencySource::Bitbucket { path, .. }
+            | DependencySource::Git { path, .. }
+            | DependencySource::Gist { file: path, .. } => {
+                if let Some(unescaped) = utils::percent_decode(path) {
+                    *path = unescaped;
+                }
+            }
+            DependencySource::Url(_) | DependencySource::Local { .. } => {}
+        }
+    }
+
+    fn apply_query(&mut self, query: &str) {
+        if let DependencySource::GitHub {
+            version,
+            follow_links,
+            assets,
+            ..
+        }
+        | DependencySource::GitLab {
+            version,
+            follow_links,
+            assets,
+            ..
+        }
+        | DependencySource::Gitea {
+            version,
+            follow_links,
+            assets,
+            ..
+        }
+        | DependencySource::Bitbucket {
+            version,
+            follow_links,
+            assets,
+            ..
+        }
+        | DependencySource::Gist {
+            version,
+            follow_links,
+            assets,
+            ..
+        }
+        | DependencySource::Git {
+            version,
+            follow_links,
+            assets,
+            ..
+        } = self
+        {
+            for pair in query.split('&') {
+                match pair.split_once('=') {
+                    Some(("tag", tag)) => *version = Some(Version::Tag(tag.to_string())),
+                    Some(("branch", branch)) => *version = Some(Version::Branch(branch.to_string())),
+                    Some(("rev", rev)) => *version = Some(Version::Commit(rev.to_string())),
+                    Some(("follow-links", depth)) => *follow_links = depth.parse().ok(),
+                    Some(("assets", value)) => *assets = value == "true",
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// What the source points at, ignoring the requested version. Two dependencies with
+    /// the same identity but different versions conflict.
+    pub fn identity(&self) -> String {
+        match self {
+            DependencySource::GitHub {
+                owner, repo, path, ..
+            } => format!("github:{}/{}/{}", owner, repo, escape_path(path)),
+            // 自建实例的地址属于身份的一部分
+            DependencySource::GitLab {
+                host,
+                project,
+                path,
+                ..
+            } if host != gitlab::DEFAULT_HOST => {
+                format!("gitlab:{}/-/{}?host={}", project, escape_path(path), host)
+            }
+            DependencySource::GitLab { project, path, .. } => format!("gitlab:{}/-/{}", project, escape_path(path)),
+            DependencySource::Gitea {
+                host,
+                owner,
+                repo,
+                path,
+                ..
+            } => match host.as_str() {
+                gitea::DEFAULT_HOST => format!("gitea:{}/{}/{}", owner, repo, escape_path(path)),
+                gitea::CODEBERG_HOST => {
+                    format!("forgejo:{}/{}/{}", owner, repo, escape_path(path))
+                }
+                _ => format!("gitea:{}/{}/{}?host={}", owner, repo, escape_path(path), host),
+            },
+            DependencySource::Bitbucket {
+                host,
+                owner,
+                repo,
+                path,
+                ..
+            } if host != bitbucket::CLOUD_HOST => {
+                format!("bitbucket:{}/{}/{}?host={}", owner, repo, escape_path(path), host)
+            }
+            DependencySource::Bitbucket {
+                owner, repo, path, ..
+            } => format!("bitbucket:{}/{}/{}", owner, repo, escape_path(path)),
+            DependencySource::Gist { id, file, .. } => format!("gist:{}/{}", id, escape_path(file)),
+            DependencySource::Git { url, path, .. } => format!("git+{}#{}", url, escape_path(path)),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Base URL of a GitLab, Gitea or Bitbucket source's host.
+    pub fn host(&self) -> Option<&str> {
+        match self {
+            DependencySource::GitLab { host, .. }
+            | DependencySource::Gitea { host, .. }
+            | DependencySource::Bitbucket { host, .. } => Some(host),
+            _ => None,
+        }
+    }
+
+    /// The repository a git source reads from, for messages: `owner/repo` or the clone URL.
+    pub fn repository(&self) -> Option<String> {
+        match self {
+            DependencySource::GitHub { owner, repo, .. } => Some(format!("{}/{}", owner, repo)),
+            DependencySource::GitLab { host, project, .. } => Some(format!("{}/{}", host, project)),
+            DependencySource::Gitea {
+                host, owner, repo, ..
+            }
+            | DependencySource::Bitbucket {
+                host, owner, repo, ..
+            } => Some(format!("{}/{}/{}", host, owner, repo)),
+            DependencySource::Gist { id, .. } => Some(format!("gist:{}", id)),
+            DependencySource::Git { url, .. } => Some(url.clone()),
+            DependencySource::Url(_) | DependencySource::Local { .. } => None,
+        }
+    }
+
+    /// Owner and repository name used by layouts; for clone URLs, the last two path segments.
+    pub fn owner_and_repo(&self) -> Option<(String, String)> {
+        match self {
+            DependencySource::GitHub { owner, repo, .. }
+            | DependencySource::Gitea { owner, repo, .. }
+            | DependencySource::Bitbucket { owner, repo, .. } => {
+                Some((owner.clone(), repo.clone()))
+            }
+            // 嵌套的组以 `-` 连接成一级
+            DependencySource::GitLab { project, .. } => {
+                let (namespace, name) = project.rsplit_once('/').unwrap_or(("", project));
+                Some((namespace.replace('/', "-"), name.to_string()))
+            }
+            // 按 by-repo 布局放在 gist-<id>/ 下
+            DependencySource::Gist { id, .. } => Some(("gist".to_string(), id.clone())),
+            DependencySource::Git { url, .. } => {
+                let trimmed = url.trim_end_matches('/');
+                let trimmed = trimmed.strip_suffix(".git").unwrap_or(trimmed);
+                let mut segments = trimmed.rsplit(['/', ':']);
+                let repo = segments.next().unwrap_or_default();
+                let owner = segments.next().unwrap_or_default();
+                Some((owner.to_string(), repo.to_string()))
+            }
+            DependencySource::Url(_) | DependencySource::Local { .. } => None,
+        }
+    }
+
+    /// Another file of the same repository, pinned to `commit`.
+    pub fn in_same_repository(&self, path: &str, commit: &str) -> Option<Self> {
+        let version = Some(Version::Commit(commit.to_string()));
+        match self {
+            DependencySource::GitHub { owner, repo, .. } => Some(DependencySource::GitHub {
+                owner: owner.clone(),
+                repo: repo.clone(),
+                path: path.to_string(),
+                version,
+                follow_links: None,
+                assets: false,
+            }),
+            DependencySource::GitLab { host, project, .. } => Some(DependencySource::GitLab {
+                host: host.clone(),
+                project: project.clone(),
+                path: path.to_string(),
+                version,
+                follow_links: None,
+                assets: false,
+            }),
+            DependencySource::Gitea {
+                host, owner, repo, ..
+            } => Some(DependencySource::Gitea {
+                host: host.clone(),
+                owner: owner.clone(),
+                repo: repo.clone(),
+                path: path.to_string(),
+                version,
+                follow_links: None,
+                assets: false,
+            }),
+            DependencySource::Bitbucket {
+                host, owner, repo, ..
+            } => Some(DependencySource::Bitbucket {
+                host: host.clone(),
+                owner: owner.clone(),
+                repo: repo.clone(),
+                path: path.to_string(),
+                version,
+                follow_links: None,
+                assets: false,
+            }),
+            DependencySource::Gist { id, .. } => Some(DependencySource::Gist {
+                id: id.clone(),
+                file: path.to_string(),
+                version,
+                follow_links: None,
+                assets: false,
+            }),
+            DependencySource::Git { url, .. } => Some(DependencySource::Git {
+                url: url.clone(),
+                path: path.to_string(),
+                version,
+                follow_links: None,
+                assets: false,
+            }),
+            DependencySource::Url(_) | DependencySource::Local { .. } => None,
+        }
+    }
+
+    /// Page showing `file_path` at `commit` in a browser, where the host has one.
+    pub fn web_url(&self, commit: &str, file_path: &str) -> Option<String> {
+        match self {
+            DependencySource::GitHub { owner, repo, .. } => Some(format!(
+                "https://github.com/{}/{}/blob/{}/{}",
+                owner, repo, commit, file_path
+            )),
+            DependencySource::GitLab { host, project, .. } => {
+                Some(gitlab::web_url(host, project, commit, file_path))
+            }
+            DependencySource::Gitea {
+                host, owner, repo, ..
+            } => Some(gitea::web_url(host, owner, repo, commit, file_path)),
+            DependencySource::Bitbucket {
+                host, owner, repo, ..
+            } => Some(bitbucket::web_url(host, owner, repo, commit, file_path)),
+            DependencySource::Gist { id, .. } => Some(gist::web_url(id, commit, file_path)),
+            _ => None,
+        }
+    }
+
     fn parse_github_simple(s: &str) -> Result<Self, DependencyError> {
         let (owner, repo, path) = Self::parse_github_path(s)?;
+        Self::tree_matcher(&path)?;
         Ok(DependencySource::GitHub {
             owner,
             repo,
             path,
-            version: None, // 默认使用 main/master 分支
+            version: None, // 默认分支通过 GitHub API 解析
+            follow_links: None,
+            assets: false,
+        })
+    }
+
+    /// Parses the string form of a source that accepts a `host`, or `None` for other schemes.
+    fn parse_hosted_simple(s: &str) -> Option<Result<Self, DependencyError>> {
+        if let Some(gitlab_str) = s.strip_prefix("gitlab:") {
+            Some(Self::parse_gitlab_simple(gitlab_str))
+        } else if let Some((gitea_str, host)) = Self::strip_gitea_scheme(s) {
+            Some(Self::parse_gitea_simple(gitea_str, host))
+        } else {
+            s.strip_prefix("bitbucket:").map(Self::parse_bitbucket_simple)
+        }
+    }
+
+    fn parse_gitlab_simple(s: &str) -> Result<Self, DependencyError> {
+        let (project, path) = Self::parse_gitlab_path(s)?;
+        Self::tree_matcher(path)?;
+        Ok(DependencySource::GitLab {
+            host: gitlab::DEFAULT_HOST.to_string(),
+            project: project.to_string(),
+            path: path.to_string(),
+            version: None,
+            follow_links: None,
+            assets: false,
+        })
+    }
+
+    fn parse_gitea_simple(s: &str, host: &str) -> Result<Self, DependencyError> {
+        let (owner, repo, path) = Self::parse_github_path(s)?;
+        Self::tree_matcher(&path)?;
+        Ok(DependencySource::Gitea {
+            host: host.to_string(),
+            owner,
+            repo,
+            path,
+            version: None,
+            follow_links: None,
+            assets: false,
+        })
+    }
+
+    fn parse_bitbucket_simple(s: &str) -> Result<Self, DependencyError> {
+        let (owner, repo, path) = Self::parse_github_path(s)?;
+        Self::tree_matcher(&path)?;
+        Ok(DependencySource::Bitbucket {
+            host: bitbucket::CLOUD_HOST.to_string(),
+            owner,
+            repo,
+            path,
+            version: None,
+            follow_links: None,
+            assets: false,
         })
     }
 
+    fn parse_gist_simple(s: &str) -> Result<Self, DependencyError> {
+        let (id, file) = s
+            .split_once('/')
+            .filter(|(id, file)| {
+                !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()) && !file.is_empty()
+            })
+            .filter(|(_, file)| !file.contains('/'))
+            .ok_or_else(|| {
+                DependencyError::InvalidFormat(format!("Expected format: gist:<id>/<file>, got: {}", s))
+            })?;
+        Self::tree_matcher(file)?;
+        Ok(DependencySource::Gist {
+            id: id.to_string(),
+            file: file.to_string(),
+            version: None,
+            follow_links: None,
+            assets: false,
+        })
+    }
+
+    fn parse_gitlab_path(s: &str) -> Result<(&str, &str), DependencyError> {
+        // 项目可能位于嵌套的组中：group/subgroup/project/-/path/to/file.md
+        // 没有 `/-/` 时与 GitHub 相同，取前两段为项目
+        let (project, path) = match s.split_once("/-/") {
+            Some(split) => split,
+            None => {
+                let mut slashes = s.match_indices('/').map(|(i, _)| i);
+                match (slashes.next(), slashes.next()) {
+                    (Some(_), Some(i)) => (&s[..i], &s[i + 1..]),
+                    _ => ("", ""),
+                }
+            }
+        };
+        if !project.contains('/') || project.split('/').any(str::is_empty) || path.is_empty() {
+            return Err(DependencyError::InvalidGitLabPath(format!(
+                "Expected format: group/project/path/to/file.md or group/subgroup/project/-/path/to/file.md, got: {}",
+                s
+            )));
+        }
+        Ok((project, path))
+    }
+
     fn parse_github_path(s: &str) -> Result<(String, String, String), DependencyError> {
         // 格式: owner/repo/path/to/file.md
         let parts: Vec<&str> = s.split('/').collect();
@@ -112,60 +967,293 @@ impl DependencySource {
         Ok((owner, repo, path))
     }
 
-    pub fn to_raw_url(&self) -> String {
+    fn tree_matcher(pattern: &str) -> Result<GlobMatcher, DependencyError> {
+        GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map(|glob| glob.compile_matcher())
+            .map_err(|e| DependencyError::InvalidFormat(format!("Invalid glob {}: {}", pattern, e)))
+    }
+
+    /// GitHub sources naming a directory (trailing `/`) or a glob expand to many files.
+    pub fn is_tree(&self) -> bool {
         match self {
-            DependencySource::GitHub {
-                owner,
-                repo,
-                path,
-                version,
-            } => {
-                let ref_part = match version {
-                    Some(Version::Tag(t)) => t,
-                    Some(Version::Branch(b)) => b,
-                    Some(Version::Commit(c)) => c,
-                    None => "main", // 默认分支
-                };
-                format!(
-                    "https://raw.githubusercontent.com/{}/{}/{}/{}",
-                    owner, repo, ref_part, path
-                )
+            DependencySource::GitHub { path, .. }
+            | DependencySource::GitLab { path, .. }
+            | DependencySource::Gitea { path, .. }
+            | DependencySource::Bitbucket { path, .. }
+            | DependencySource::Gist { file: path, .. }
+            | DependencySource::Git { path, .. } => {
+                path.is_empty() || path.ends_with('/') || path.contains(GLOB_CHARS)
             }
+            _ => false,
+        }
+    }
+
+    /// Filters repository file paths down to the ones selected by a directory or glob source.
+    pub fn select_files(&self, paths: &[String]) -> Result<Vec<String>, DependencyError> {
+        let (DependencySource::GitHub { path, .. }
+        | DependencySource::GitLab { path, .. }
+        | DependencySource::Gitea { path, .. }
+        | DependencySource::Bitbucket { path, .. }
+        | DependencySource::Gist { file: path, .. }
+        | DependencySource::Git { path, .. }) = self
+        else {
+            return Ok(Vec::new());
+        };
+
+        if path.contains(GLOB_CHARS) {
+            let matcher = Self::tree_matcher(path)?;
+            Ok(paths.iter().filter(|p| matcher.is_match(p)).cloned().collect())
+        } else {
+            Ok(paths
+                .iter()
+                .filter(|p| p.starts_with(path.as_str()))
+                .cloned()
+                .collect())
+        }
+    }
+
+    /// The ref requested in the manifest, or `None` to use the repository's default branch.
+    pub fn git_ref(&self) -> Option<&str> {
+        match self {
+            DependencySource::GitHub { version, .. }
+            | DependencySource::GitLab { version, .. }
+            | DependencySource::Gitea { version, .. }
+            | DependencySource::Bitbucket { version, .. }
+            | DependencySource::Gist { version, .. }
+            | DependencySource::Git { version, .. } => match version {
+                Some(Version::Tag(t)) => Some(t),
+                Some(Version::Branch(b)) => Some(b),
+                Some(Version::Commit(c)) => Some(c),
+                None => None,
+            },
+            DependencySource::Url(_) | DependencySource::Local { .. } => None,
+        }
+    }
+
+    pub fn fetches_assets(&self) -> bool {
+        matches!(
+            self,
+            DependencySource::GitHub { assets: true, .. }
+                | DependencySource::GitLab { assets: true, .. }
+                | DependencySource::Gitea { assets: true, .. }
+                | DependencySource::Bitbucket { assets: true, .. }
+                | DependencySource::Gist { assets: true, .. }
+                | DependencySource::Git { assets: true, .. }
+        )
+    }
+
+    /// Link depth requested with `follow-links`, if any.
+    pub fn follow_links(&self) -> Option<usize> {
+        match self {
+            DependencySource::GitHub { follow_links, .. }
+            | DependencySource::GitLab { follow_links, .. }
+            | DependencySource::Gitea { follow_links, .. }
+            | DependencySource::Bitbucket { follow_links, .. }
+            | DependencySource::Gist { follow_links, .. }
+            | DependencySource::Git { follow_links, .. } => *follow_links,
+            _ => None,
+        }
+    }
+
+    /// Raw download URL with the ref replaced by `git_ref` (usually a resolved commit SHA).
+    pub fn raw_url_at(&self, git_ref: &str) -> String {
+        match self {
+            DependencySource::GitHub { path, .. }
+            | DependencySource::GitLab { path, .. }
+            | DependencySource::Gitea { path, .. }
+            | DependencySource::Bitbucket { path, .. }
+            | DependencySource::Gist { file: path, .. }
+            | DependencySource::Git { path, .. } => self.raw_file_url(git_ref, path),
             DependencySource::Url(url) => url.clone(),
+            DependencySource::Local { path, .. } => path.clone(),
+        }
+    }
+
+    /// Raw download URL of another file in the same repository.
+    pub fn raw_file_url(&self, git_ref: &str, file_path: &str) -> String {
+        match self {
+            DependencySource::GitHub { owner, repo, .. } => format!(
+                "https://raw.githubusercontent.com/{}/{}/{}/{}",
+                owner, repo, git_ref, file_path
+            ),
+            DependencySource::GitLab { host, project, .. } => {
+                gitlab::raw_file_url(host, project, git_ref, file_path)
+            }
+            DependencySource::Gitea {
+                host, owner, repo, ..
+            } => gitea::raw_file_url(host, owner, repo, git_ref, file_path),
+            DependencySource::Bitbucket {
+                host, owner, repo, ..
+            } => bitbucket::raw_file_url(host, owner, repo, git_ref, file_path),
+            DependencySource::Gist { id, .. } => gist::raw_file_url(id, git_ref, file_path),
+            // 其他仓库的文件通过本地克隆读取，以定位符记录
+            DependencySource::Git { url, .. } => git::file_locator(url, git_ref, file_path),
+            _ => self.raw_url_at(git_ref),
+        }
+    }
+
+    /// Repository path of a file downloaded from `raw_file_url(git_ref, ...)`; `None` if
+    /// `url` does not point into this repository at `git_ref`.
+    pub fn repo_file_path(&self, git_ref: &str, url: &str) -> Option<String> {
+        match self {
+            DependencySource::GitLab { host, project, .. } => {
+                gitlab::file_path(host, project, git_ref, url)
+            }
+            DependencySource::Gitea {
+                host, owner, repo, ..
+            } => gitea::file_path(host, owner, repo, git_ref, url),
+            DependencySource::Bitbucket {
+                host, owner, repo, ..
+            } => bitbucket::file_path(host, owner, repo, git_ref, url),
+            DependencySource::Gist { id, .. } => gist::file_path(id, git_ref, url),
+            DependencySource::GitHub { .. } | DependencySource::Git { .. } => url
+                .strip_prefix(&self.raw_file_url(git_ref, ""))
+                .map(str::to_string),
+            DependencySource::Url(_) | DependencySource::Local { .. } => None,
         }
     }
 
     pub fn file_name(&self) -> String {
         match self {
-            DependencySource::GitHub { path, .. } => {
-                path.split('/').last().unwrap_or("file.md").to_string()
+            DependencySource::GitHub { path, .. }
+            | DependencySource::GitLab { path, .. }
+            | DependencySource::Gitea { path, .. }
+            | DependencySource::Bitbucket { path, .. }
+            | DependencySource::Gist { file: path, .. }
+            | DependencySource::Git { path, .. } => {
+                path.split('/').next_back().unwrap_or("file.md").to_string()
             }
-            DependencySource::Url(url) => {
-                url.split('/').last().unwrap_or("file.md").to_string()
+            // 查询参数和片段不属于文件名
+            DependencySource::Url(url) => url
+                .split(['?', '#'])
+                .next()
+                .and_then(|path| path.split('/').next_back())
+                .unwrap_or("file.md")
+                .to_string(),
+            DependencySource::Local { path, .. } => path
+                .trim_end_matches('/')
+                .split('/')
+                .next_back()
+                .unwrap_or("file.md")
+                .to_string(),
+        }
+    }
+
+    /// Directory in the source that the dependency's files are placed relative to.
+    pub fn root_dir(&self) -> String {
+        match self {
+            DependencySource::GitHub { path, .. }
+            | DependencySource::GitLab { path, .. }
+            | DependencySource::Gitea { path, .. }
+            | DependencySource::Bitbucket { path, .. }
+            | DependencySource::Gist { file: path, .. }
+            | DependencySource::Git { path, .. } => {
+                // 目录或 glob 依赖以第一个通配符之前的目录为根
+                let prefix = if self.is_tree() {
+                    &path[..path.find(GLOB_CHARS).unwrap_or(path.len())]
+                } else {
+                    path.as_str()
+                };
+                prefix.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("").to_string()
             }
+            DependencySource::Url(_) | DependencySource::Local { .. } => String::new(),
         }
     }
 
-    pub fn output_path(&self, _name: &str) -> String {
+    /// Source path of the dependency's own file; `None` for directories and globs.
+    pub fn main_path(&self) -> Option<String> {
+        match self {
+            _ if self.is_tree() => None,
+            DependencySource::GitHub { path, .. }
+            | DependencySource::GitLab { path, .. }
+            | DependencySource::Gitea { path, .. }
+            | DependencySource::Bitbucket { path, .. }
+            | DependencySource::Gist { file: path, .. }
+            | DependencySource::Git { path, .. } => Some(path.clone()),
+            DependencySource::Url(_) | DependencySource::Local { .. } => Some(self.file_name()),
+        }
+    }
+}
+
+/// Escapes the characters that delimit the query in a source id, and `%` itself, so that
+/// a path such as the glob `docs/a?.md` survives `from_id`.
+fn escape_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '%' | '?' | '&' | '#' => escaped.push_str(&format!("%{:02X}", c as u8)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl fmt::Display for DependencySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             DependencySource::GitHub {
-                owner,
-                repo,
-                path,
+                version,
+                follow_links,
+                assets,
+                ..
+            }
+            | DependencySource::GitLab {
+                version,
+                follow_links,
+                assets,
+                ..
+            }
+            | DependencySource::Gitea {
+                version,
+                follow_links,
+                assets,
+                ..
+            }
+            | DependencySource::Bitbucket {
+                version,
+                follow_links,
+                assets,
+                ..
+            }
+            | DependencySource::Gist {
+                version,
+                follow_links,
+                assets,
+                ..
+            }
+            | DependencySource::Git {
+                version,
+                follow_links,
+                assets,
                 ..
             } => {
-                // 创建类似 owner-repo/path/to/file.md 的路径
-                let dir_part = if path.contains('/') {
-                    let dir = path.rsplitn(2, '/').nth(1).unwrap_or("");
-                    format!("{}-{}/{}", owner, repo, dir)
+                let identity = self.identity();
+                write!(f, "{}", identity)?;
+                let mut query = Vec::new();
+                match version {
+                    Some(Version::Tag(t)) => query.push(format!("tag={}", t)),
+                    Some(Version::Branch(b)) => query.push(format!("branch={}", b)),
+                    Some(Version::Commit(c)) => query.push(format!("rev={}", c)),
+                    None => {}
+                }
+                if let Some(depth) = follow_links {
+                    query.push(format!("follow-links={}", depth));
+                }
+                if *assets {
+                    query.push("assets=true".to_string());
+                }
+                if query.is_empty() {
+                    Ok(())
                 } else {
-                    format!("{}-{}", owner, repo)
-                };
-                format!("{}/{}", dir_part, self.file_name())
-            }
-            DependencySource::Url(_) => {
-                self.file_name()
+                    // 自建实例的身份已带有查询参数
+                    let separator = if identity.contains('?') { '&' } else { '?' };
+                    write!(f, "{}{}", separator, query.join("&"))
+                }
             }
+            DependencySource::Url(url) => write!(f, "{}", url),
+            DependencySource::Local { path, .. } => write!(f, "path:{}", path),
         }
     }
 }
@@ -179,6 +1267,7 @@ mod tests {
         let value = toml::Value::String("github:rust-lang/book/src/ch01.md".to_string());
         let dep = DependencySource::parse(&value).unwrap();
         
+        assert_eq!(dep.git_ref(), None);
         if let DependencySource::GitHub { owner, repo, path, .. } = dep {
             assert_eq!(owner, "rust-lang");
             assert_eq!(repo, "book");
@@ -219,4 +1308,325 @@ mod tests {
             panic!("Expected GitHub source");
         }
     }
+
+    #[test]
+    fn test_parse_local_path() {
+        let value: toml::Value = toml::from_str(
+            r#"guide = { path = "../shared-docs/", symlink = true }"#,
+        )
+        .unwrap();
+        let dep = DependencySource::parse(&value["guide"]).unwrap();
+
+        assert_eq!(
+            dep,
+            DependencySource::Local {
+                path: "../shared-docs/".to_string(),
+                symlink: true,
+            }
+        );
+        assert_eq!(dep.file_name(), "shared-docs");
+        assert_eq!(dep.to_string(), "path:../shared-docs/");
+    }
+
+    #[test]
+    fn test_url_file_name() {
+        let url = "https://example.com/docs/README.md?raw=true#top";
+        let dep = DependencySource::Url(url.to_string());
+        assert_eq!(dep.file_name(), "README.md");
+        assert_eq!(dep.main_path().as_deref(), Some("README.md"));
+    }
+
+    #[test]
+    fn test_source_id_roundtrip() {
+        let value: toml::Value = toml::from_str(
+            r#"api = { git = "github:owner/repo/docs/api.md", tag = "v1.0", follow-links = 2, assets = true }"#,
+        )
+        .unwrap();
+        let dep = DependencySource::parse(&value["api"]).unwrap();
+
+        let id = dep.to_string();
+        assert_eq!(id, "github:owner/repo/docs/api.md?tag=v1.0&follow-links=2&assets=true");
+        assert_eq!(DependencySource::from_id(&id).unwrap(), dep);
+        assert_eq!(dep.identity(), "github:owner/repo/docs/api.md");
+
+        let url = DependencySource::from_id("https://example.com/a.md").unwrap();
+        assert_eq!(url, DependencySource::Url("https://example.com/a.md".to_string()));
+
+        // 路径中的 `?`、`&`、`#`、`%` 不能被当成查询参数
+        for spec in [
+            r#"{ git = "github:owner/repo/docs/a?.md", branch = "main" }"#,
+            r#"{ git = "gitlab:group/project/-/docs/a?.md", host = "https://gl.example.com", tag = "v1" }"#,
+            r#"{ git = "https://git.example.com/docs.git", path = "docs/a?#&%.md", rev = "abc" }"#,
+        ] {
+            let value: toml::Value = toml::from_str(&format!("d = {}", spec)).unwrap();
+            let glob = DependencySource::parse(&value["d"]).unwrap();
+            assert_eq!(DependencySource::from_id(&glob.to_string()).unwrap(), glob);
+        }
+    }
+
+    #[test]
+    fn test_parse_gitlab() {
+        let value: toml::Value = toml::from_str(
+            r#"
+simple = "gitlab:group/project/docs/api.md"
+nested = { git = "gitlab:group/sub/project/-/docs/api.md", tag = "v1", host = "https://gitlab.example.com/" }
+glob = { git = "gitlab:group/sub/project", glob = "docs/*.md" }
+bad = "gitlab:project/-/a.md"
+"#,
+        )
+        .unwrap();
+
+        let simple = DependencySource::parse(&value["simple"]).unwrap();
+        assert_eq!(simple.to_string(), "gitlab:group/project/-/docs/api.md");
+        assert_eq!(DependencySource::from_id(&simple.to_string()).unwrap(), simple);
+
+        let nested = DependencySource::parse(&value["nested"]).unwrap();
+        let DependencySource::GitLab { host, project, path, .. } = &nested else {
+            panic!("Expected GitLab source");
+        };
+        assert_eq!(host, "https://gitlab.example.com");
+        assert_eq!(project, "group/sub/project");
+        assert_eq!(path, "docs/api.md");
+        assert_eq!(
+            nested.owner_and_repo(),
+            Some(("group-sub".to_string(), "project".to_string()))
+        );
+        let id = nested.to_string();
+        assert_eq!(
+            id,
+            "gitlab:group/sub/project/-/docs/api.md?host=https://gitlab.example.com&tag=v1"
+        );
+        assert_eq!(DependencySource::from_id(&id).unwrap(), nested);
+
+        let url = nested.raw_file_url("0123abc", "docs/a b.md");
+        assert_eq!(nested.repo_file_path("0123abc", &url).as_deref(), Some("docs/a b.md"));
+
+        let glob = DependencySource::parse(&value["glob"]).unwrap();
+        assert!(glob.is_tree());
+        assert_eq!(glob.root_dir(), "docs");
+        assert!(DependencySource::parse(&value["bad"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_gitea() {
+        let value: toml::Value = toml::from_str(
+            r#"
+codeberg = "forgejo:org/docs/guide/api.md"
+hosted = { git = "gitea:org/docs", glob = "guide/*.md", branch = "dev", host = "https://git.example.com" }
+bad = { git = "github:org/docs/a.md", host = "https://git.example.com" }
+"#,
+        )
+        .unwrap();
+
+        let codeberg = DependencySource::parse(&value["codeberg"]).unwrap();
+        let DependencySource::Gitea { host, .. } = &codeberg else {
+            panic!("Expected Gitea source");
+        };
+        assert_eq!(host, gitea::CODEBERG_HOST);
+        assert_eq!(codeberg.to_string(), "forgejo:org/docs/guide/api.md");
+        assert_eq!(DependencySource::from_id(&codeberg.to_string()).unwrap(), codeberg);
+        assert_eq!(
+            codeberg.web_url("0123abc", "guide/b.md").as_deref(),
+            Some("https://codeberg.org/org/docs/src/commit/0123abc/guide/b.md")
+        );
+
+        let hosted = DependencySource::parse(&value["hosted"]).unwrap();
+        assert!(hosted.is_tree());
+        let id = hosted.to_string();
+        assert_eq!(
+            id,
+            "gitea:org/docs/guide/*.md?host=https://git.example.com&branch=dev"
+        );
+        assert_eq!(DependencySource::from_id(&id).unwrap(), hosted);
+        let url = hosted.raw_file_url("0123abc", "guide/a.md");
+        assert_eq!(
+            url,
+            "https://git.example.com/api/v1/repos/org/docs/raw/guide/a.md?ref=0123abc"
+        );
+        assert_eq!(hosted.repo_file_path("0123abc", &url).as_deref(), Some("guide/a.md"));
+        assert!(DependencySource::parse(&value["bad"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_bitbucket() {
+        let value: toml::Value = toml::from_str(
+            r#"
+cloud = "bitbucket:team/runbooks/ops/deploy.md"
+server = { git = "bitbucket:OPS/runbooks/ops", glob = "*.md", tag = "v2", host = "https://bitbucket.example.com" }
+"#,
+        )
+        .unwrap();
+
+        let cloud = DependencySource::parse(&value["cloud"]).unwrap();
+        assert_eq!(cloud.to_string(), "bitbucket:team/runbooks/ops/deploy.md");
+        assert_eq!(DependencySource::from_id(&cloud.to_string()).unwrap(), cloud);
+        assert_eq!(
+            cloud.web_url("0123abc", "ops/b.md").as_deref(),
+            Some("https://bitbucket.org/team/runbooks/src/0123abc/ops/b.md")
+        );
+
+        let server = DependencySource::parse(&value["server"]).unwrap();
+        assert!(server.is_tree());
+        assert_eq!(server.git_ref(), Some("v2"));
+        let id = server.to_string();
+        assert_eq!(
+            id,
+            "bitbucket:OPS/runbooks/ops/*.md?host=https://bitbucket.example.com&tag=v2"
+        );
+        assert_eq!(DependencySource::from_id(&id).unwrap(), server);
+        assert_eq!(
+            server.web_url("0123abc", "ops/b.md").as_deref(),
+            Some("https://bitbucket.example.com/projects/OPS/repos/runbooks/browse/ops/b.md?at=0123abc")
+        );
+        let url = server.raw_file_url("0123abc", "ops/a.md");
+        assert_eq!(server.repo_file_path("0123abc", &url).as_deref(), Some("ops/a.md"));
+    }
+
+    #[test]
+    fn test_parse_gist() {
+        let value: toml::Value = toml::from_str(
+            r#"
+simple = "gist:aa5a315d61ae9438b18d/checklist.md"
+pinned = { gist = "aa5a315d61ae9438b18d", file = "*.md", rev = "0123abc" }
+tagged = { gist = "aa5a315d61ae9438b18d", file = "checklist.md", tag = "v1" }
+bad = "gist:aa5a315d61ae9438b18d"
+"#,
+        )
+        .unwrap();
+
+        let simple = DependencySource::parse(&value["simple"]).unwrap();
+        assert_eq!(simple.to_string(), "gist:aa5a315d61ae9438b18d/checklist.md");
+        assert_eq!(simple.main_path().as_deref(), Some("checklist.md"));
+        assert_eq!(
+            simple.owner_and_repo(),
+            Some(("gist".to_string(), "aa5a315d61ae9438b18d".to_string()))
+        );
+
+        let pinned = DependencySource::parse(&value["pinned"]).unwrap();
+        assert!(pinned.is_tree());
+        assert_eq!(pinned.git_ref(), Some("0123abc"));
+        let id = pinned.to_string();
+        assert_eq!(id, "gist:aa5a315d61ae9438b18d/*.md?rev=0123abc");
+        assert_eq!(DependencySource::from_id(&id).unwrap(), pinned);
+        let url = pinned.raw_file_url("0123abc", "prompt.md");
+        assert_eq!(pinned.repo_file_path("0123abc", &url).as_deref(), Some("prompt.md"));
+
+        assert!(DependencySource::parse(&value["tagged"]).is_err());
+        assert!(DependencySource::parse(&value["bad"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_git_url() {
+        let value: toml::Value = toml::from_str(
+            r#"
+api = { git = "https://git.example.com/org/docs.git", path = "guide/api.md", tag = "v1.0" }
+all = { git = "file:///srv/repos/docs.git", path = "guide", glob = "*.md" }
+bad = { git = "ssh://git@git.example.com/org/docs.git" }
+option = { git = "file:///srv/repos/docs.git", path = "a.md", branch = "--upload-pack=touch x" }
+option_url = { git = "--upload-pack=x://host/repo", path = "a.md" }
+"#,
+        )
+        .unwrap();
+
+        let api = DependencySource::parse(&value["api"]).unwrap();
+        assert_eq!(api.git_ref(), Some("v1.0"));
+        assert_eq!(api.main_path().as_deref(), Some("guide/api.md"));
+        assert_eq!(
+            api.owner_and_repo(),
+            Some(("org".to_string(), "docs".to_string()))
+        );
+        assert_eq!(
+            api.raw_file_url("0123abc", "guide/b.md"),
+            "git+https://git.example.com/org/docs.git#0123abc:guide/b.md"
+        );
+
+        let id = api.to_string();
+        assert_eq!(id, "git+https://git.example.com/org/docs.git#guide/api.md?tag=v1.0");
+        assert_eq!(DependencySource::from_id(&id).unwrap(), api);
+
+        let all = DependencySource::parse(&value["all"]).unwrap();
+        assert!(all.is_tree());
+        assert_eq!(all.root_dir(), "guide");
+        assert!(DependencySource::parse(&value["bad"]).is_err());
+        assert!(DependencySource::parse(&value["option"]).is_err());
+        assert!(DependencySource::parse(&value["option_url"]).is_err());
+        assert!(DependencySource::from_id("git+file:///srv/repos/docs.git#a.md?branch=--upload-pack=x").is_err());
+    }
+
+    #[test]
+    fn test_parse_github_glob() {
+        let value: toml::Value = toml::from_str(
+            r#"skills = { git = "github:anthropics/skills", glob = "skills/*/SKILL.md" }"#,
+        )
+        .unwrap();
+        let dep = DependencySource::parse(&value["skills"]).unwrap();
+
+        assert!(dep.is_tree());
+        assert_eq!(dep.root_dir(), "skills");
+        assert_eq!(dep.main_path(), None);
+        let paths = vec![
+            "README.md".to_string(),
+            "skills/pdf/SKILL.md".to_string(),
+            "skills/pdf/reference.md".to_string(),
+            "skills/pdf/nested/SKILL.md".to_string(),
+        ];
+        assert_eq!(
+            dep.select_files(&paths).unwrap(),
+            vec!["skills/pdf/SKILL.md".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_parse_github_directory() {
+        let value = toml::Value::String("github:owner/repo/docs/".to_string());
+        let dep = DependencySource::parse(&value).unwrap();
+
+        assert!(dep.is_tree());
+        assert_eq!(dep.root_dir(), "docs");
+        let paths = vec!["docs/a.md".to_string(), "docsx/b.md".to_string()];
+        assert_eq!(dep.select_files(&paths).unwrap(), vec!["docs/a.md".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_integrity() {
+        let value: toml::Value = toml::from_str(
+            r#"
+a = { url = "https://example.com/a.md", sha256 = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD" }
+b = { git = "github:owner/repo/b.md", integrity = "sha256-ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=" }
+c = { url = "https://example.com/c.md", sha256 = "abc" }
+"#,
+        )
+        .unwrap();
+
+        let a = Dependency::parse(&value["a"]).unwrap();
+        let b = Dependency::parse(&value["b"]).unwrap();
+        let expected = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(a.source, DependencySource::Url("https://example.com/a.md".to_string()));
+        assert_eq!(a.sha256.as_deref(), Some(expected));
+        assert_eq!(b.sha256.as_deref(), Some(expected));
+        assert!(Dependency::parse(&value["c"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_links_mode() {
+        let value: toml::Value = toml::from_str(
+            r#"
+a = "github:owner/repo/a.md"
+b = { git = "github:owner/repo/b.md", links = "keep" }
+c = { git = "github:owner/repo/c.md", links = "relative" }
+d = { url = "https://example.com/d.md", to = "guides/d.md" }
+e = { git = "github:owner/repo/e.md", to = "" }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(Dependency::parse(&value["a"]).unwrap().links, LinkMode::Local);
+        assert_eq!(Dependency::parse(&value["b"]).unwrap().links, LinkMode::Keep);
+        assert!(Dependency::parse(&value["c"]).is_err());
+        assert_eq!(
+            Dependency::parse(&value["d"]).unwrap().to.as_deref(),
+            Some("guides/d.md")
+        );
+        assert!(Dependency::parse(&value["e"]).is_err());
+    }
 }
@@ -1,15 +1,263 @@
-use crate::dependency::DependencySource;
-use crate::github::GitHubClient;
-use anyhow::Result;
+use crate::bitbucket;
+use crate::cache::Cache;
+use crate::config;
+use crate::dependency::{Dependency, DependencySource, LinkMode};
+use crate::git::{self, GitClient, GitError};
+use crate::github::{GitHubClient, GitHubError};
+use crate::gist;
+use crate::gitea;
+use crate::gitlab;
+use crate::layout::{Layout, Placement};
+use crate::links;
+use crate::lockfile::{LockFile, LockedFile, LockedPackage};
+use crate::managed::ManagedFiles;
+use crate::plan::{self, Plan};
+use crate::utils;
+use anyhow::{Context, Result};
+use futures::stream::{self, StreamExt, TryStreamExt};
 use indicatif::{ProgressBar, ProgressStyle};
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
 
 pub struct Fetcher {
     client: GitHubClient,
+    git: GitClient,
     output_dir: String,
+    /// Directory containing markdown.toml; local path dependencies are relative to it.
+    project_dir: PathBuf,
+    cache: Option<Cache>,
+    layout: Layout,
+    tokens: ForgeTokens,
+}
+
+/// API tokens for GitLab, Gitea and Bitbucket hosts. A token from the environment goes
+/// only to the forge's public host and to hosts named in the project's own manifest, so
+/// an upstream markdown.toml cannot direct it elsewhere.
+#[derive(Debug, Default)]
+pub struct ForgeTokens {
+    /// Sent as `PRIVATE-TOKEN` to GitLab hosts without their own entry in `hosts`.
+    pub gitlab: Option<String>,
+    /// Sent as `Authorization: token` to Gitea and Forgejo hosts without their own entry.
+    pub gitea: Option<String>,
+    /// Complete `Authorization` value for Bitbucket hosts without their own entry.
+    pub bitbucket: Option<String>,
+    /// Tokens for particular hosts, keyed by base URL.
+    pub hosts: HashMap<String, String>,
+    /// Hosts used by the project's own dependencies.
+    pub declared: HashSet<String>,
+}
+
+pub const DEFAULT_JOBS: usize = 8;
+pub const DEFAULT_MAX_ASSET_SIZE: u64 = 10 * 1024 * 1024;
+/// Prefix of the directories fetches stage files in, inside the output directory.
+const STAGING_PREFIX: &str = ".cardo-staging-";
+/// File in the staging directory listing what a fetch has replaced so far.
+const JOURNAL_FILE: &str = "journal";
+
+#[derive(Debug)]
+pub struct FetchOptions {
+    /// Re-download even if the file on disk matches the lock.
+    pub force: bool,
+    /// Exact revisions to resolve instead of the manifest ref, keyed by dependency name.
+    pub precise: HashMap<String, String>,
+    /// Maximum number of dependencies fetched concurrently.
+    pub jobs: usize,
+    /// Serve files only from markdown.lock and the cache, never from the network.
+    pub offline: bool,
+    /// Fail instead of producing content that differs from markdown.lock.
+    pub locked: bool,
+    /// Largest asset fetched for `assets = true`, in bytes.
+    pub max_asset_size: u64,
+    /// Delete files written by earlier fetches that no dependency produces any more.
+    pub prune: bool,
+    /// Leave the output directory untouched if any dependency fails.
+    pub all_or_nothing: bool,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            force: false,
+            precise: HashMap::new(),
+            jobs: DEFAULT_JOBS,
+            offline: false,
+            locked: false,
+            max_asset_size: DEFAULT_MAX_ASSET_SIZE,
+            prune: true,
+            all_or_nothing: false,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum FetchError {
+    #[error("`{0}` is not in markdown.lock and cannot be resolved offline")]
+    NotLocked(String),
+    #[error("{0} is not in the download cache")]
+    NotCached(String),
+    #[error("`{0}` no longer matches markdown.lock")]
+    LockMismatch(String),
+}
+
+/// Why a dependency failed, so callers can tell stale locks from connectivity problems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    LockOutdated,
+    Network,
+    Other,
+}
+
+impl FailureKind {
+    fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            match cause.downcast_ref::<FetchError>() {
+                Some(FetchError::LockMismatch(_) | FetchError::NotLocked(_)) => {
+                    return Self::LockOutdated
+                }
+                Some(FetchError::NotCached(_)) => return Self::Network,
+                None => {}
+            }
+            match cause.downcast_ref::<GitHubError>() {
+                // 响应无法解析说明服务器答复了，不是连接问题
+                Some(GitHubError::HttpError(e)) if e.is_decode() => return Self::Other,
+                Some(GitHubError::HttpError(_) | GitHubError::NetworkError(_)) => {
+                    return Self::Network
+                }
+                _ => {}
+            }
+            if let Some(GitError::Remote(..)) = cause.downcast_ref::<GitError>() {
+                return Self::Network;
+            }
+        }
+        Self::Other
+    }
+}
+
+/// A dependency waiting to be fetched, and the package whose manifest declared it.
+struct Pending {
+    name: String,
+    dependency: Dependency,
+    parent: Option<String>,
+    /// `<repository>@commit` of the manifest that declared it.
+    manifest: Option<String>,
+}
+
+/// What a fetched dependency puts in the output directory, held back until every
+/// destination has been checked. Paths are relative to the output directory.
+enum Output {
+    /// Remote files to (re)write, with relative links rewritten.
+    Files(Vec<(LockedFile, Vec<u8>)>),
+    /// Copies of a local file or directory, replacing an earlier symlink at `destination`.
+    Copy {
+        destination: String,
+        contents: Vec<(LockedFile, Vec<u8>)>,
+    },
+    /// A local file or directory linked in place at `destination`.
+    Symlink {
+        destination: String,
+        target: PathBuf,
+        is_dir: bool,
+    },
+}
+
+/// Outcome of `fetch_all`.
+#[derive(Debug)]
+pub struct FetchReport {
+    pub results: Vec<FetchResult>,
+    /// Files from earlier fetches that were deleted, including the output directory.
+    pub pruned: Vec<String>,
+    /// Whether the output directory was changed; `false` when `all_or_nothing` held back
+    /// every write because something failed.
+    pub applied: bool,
+}
+
+/// Staged files of one dependency, as (staging path, destination) pairs.
+#[derive(Debug, Default)]
+struct Staged {
+    /// Local dependency root to clear first, and whether a symlink replaces it.
+    clear: Option<(String, bool)>,
+    moves: Vec<(PathBuf, String)>,
+}
+
+/// What committing staged files changed in the output directory, so it can be undone.
+/// It is also written to the staging directory, where the next fetch finds it if this one
+/// was interrupted.
+#[derive(Debug)]
+struct Journal {
+    /// Output directory; the file lists destinations relative to it.
+    output_dir: String,
+    /// File the entries are appended to.
+    path: PathBuf,
+    /// Directory the replaced files are moved to until the fetch has been applied.
+    backups: PathBuf,
+    /// Destinations in the order they were written, with the backup of what was there.
+    entries: Vec<(String, Option<PathBuf>)>,
+}
+
+impl Journal {
+    fn new(output_dir: &str, staging: &Path) -> Self {
+        Self {
+            output_dir: output_dir.to_string(),
+            path: staging.join(JOURNAL_FILE),
+            backups: staging.join("backup"),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Records an entry, writing it out before the output directory is changed for it.
+    async fn push(&mut self, output_path: &str, backup: Option<PathBuf>) -> Result<()> {
+        // 每行为 `目标<TAB>备份文件名`，目标相对于输出目录，没有备份时第二列为空
+        let prefix = format!("{}/", self.output_dir);
+        let relative_path = output_path.strip_prefix(&prefix).unwrap_or(output_path);
+        let backup_name = backup
+            .as_ref()
+            .and_then(|b| b.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        file.write_all(format!("{}\t{}\n", relative_path, backup_name).as_bytes())
+            .await?;
+        file.sync_data().await?;
+        self.entries.push((output_path.to_string(), backup));
+        Ok(())
+    }
+
+    /// Reads the journal an interrupted fetch left in `staging`.
+    async fn load(output_dir: &str, staging: &Path) -> Option<Self> {
+        let mut journal = Self::new(output_dir, staging);
+        let content = fs::read_to_string(&journal.path).await.ok()?;
+        journal.entries = content
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(path, backup)| {
+                let backup = (!backup.is_empty()).then(|| journal.backups.join(backup));
+                (format!("{}/{}", output_dir, path), backup)
+            })
+            .collect();
+        Some(journal)
+    }
+}
+
+/// Outcome of `cardo clean`, as paths including the output directory.
+#[derive(Debug, Default)]
+pub struct CleanReport {
+    pub removed: Vec<String>,
+    /// Files cardo did not write and therefore left in place.
+    pub kept: Vec<String>,
+}
+
+struct Resolved {
+    git_ref: Option<String>,
+    commit: Option<String>,
+    url: String,
 }
 
 #[derive(Debug)]
@@ -18,22 +266,136 @@ pub struct FetchResult {
     pub path: String,
     pub success: bool,
     pub error: Option<String>,
+    pub failure: Option<FailureKind>,
+    /// Lock entry describing what was written, set on success.
+    pub locked: Option<LockedPackage>,
+    /// Problems that did not stop the dependency, such as linked files that were skipped.
+    pub warnings: Vec<String>,
+}
+
+impl FetchResult {
+    fn ok(name: &str, path: String, locked: LockedPackage) -> Self {
+        Self {
+            name: name.to_string(),
+            path,
+            success: true,
+            error: None,
+            failure: None,
+            locked: Some(locked),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn failed(name: &str, path: String, error: anyhow::Error) -> Self {
+        Self {
+            name: name.to_string(),
+            path,
+            success: false,
+            error: Some(format!("{:#}", error)),
+            failure: Some(FailureKind::of(&error)),
+            locked: None,
+            warnings: Vec::new(),
+        }
+    }
 }
 
 impl Fetcher {
-    pub fn new(output_dir: String, github_token: Option<String>) -> Self {
+    pub fn new(output_dir: String, project_dir: PathBuf, github_token: Option<String>) -> Self {
+        let cache = Cache::from_env();
+        // 克隆与下载缓存放在一起；无法确定缓存目录时使用临时目录
+        let clones = match &cache {
+            Some(cache) => cache.root().join("git"),
+            None => std::env::temp_dir().join("cardo-git"),
+        };
         Self {
             client: GitHubClient::new(github_token),
+            git: GitClient::new(clones),
             output_dir,
+            project_dir,
+            cache,
+            layout: Layout::default(),
+            tokens: ForgeTokens::default(),
+        }
+    }
+
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_tokens(mut self, tokens: ForgeTokens) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// The token for `host`: its own from `[fetch.tokens]`, or else `default` if the host
+    /// is `public_host` or one the project's manifest names.
+    fn scoped_token<'a>(
+        &'a self,
+        host: &str,
+        default: Option<&'a String>,
+        public_host: Option<&str>,
+    ) -> Option<&'a String> {
+        let tokens = &self.tokens;
+        let trusted = public_host == Some(host) || tokens.declared.contains(host);
+        tokens.hosts.get(host).or(default.filter(|_| trusted))
+    }
+
+    /// Registers the token for the source's host, if it is a GitLab, Gitea or Bitbucket one.
+    /// Fails rather than send a token over plain http.
+    fn authorize(&self, source: &DependencySource) -> Result<()> {
+        let tokens = &self.tokens;
+        let (host, base_url, header, value) = match source {
+            DependencySource::GitLab { host, .. } => (
+                host,
+                host.as_str(),
+                "PRIVATE-TOKEN",
+                self.scoped_token(host, tokens.gitlab.as_ref(), Some(gitlab::DEFAULT_HOST))
+                    .cloned(),
+            ),
+            DependencySource::Gitea { host, .. } => (
+                host,
+                host.as_str(),
+                "Authorization",
+                // Gitea 没有统一的公共实例，codeberg.org 也需在清单中声明
+                self.scoped_token(host, tokens.gitea.as_ref(), None)
+                    .map(|token| format!("token {}", token)),
+            ),
+            // 每个主机的 token 按 HTTP access token 处理
+            DependencySource::Bitbucket { host, .. } => (
+                host,
+                bitbucket::api_base(host),
+                "Authorization",
+                match tokens.hosts.get(host) {
+                    Some(token) => Some(format!("Bearer {}", token)),
+                    None => self
+                        .scoped_token(host, tokens.bitbucket.as_ref(), Some(bitbucket::CLOUD_HOST))
+                        .cloned(),
+                },
+            ),
+            _ => return Ok(()),
+        };
+        let Some(value) = value else {
+            return Ok(());
+        };
+        if host.starts_with("http://") {
+            anyhow::bail!("Refusing to send an API token to {} over plain http", host);
         }
+        self.client.authorize(base_url, header, &value);
+        Ok(())
     }
 
+    /// Fetches `dependencies` and everything they declare in their own markdown.toml.
+    /// Results for transitive dependencies follow the direct ones, wave by wave.
     pub async fn fetch_all(
         &self,
-        dependencies: &HashMap<String, DependencySource>,
-        force: bool,
-    ) -> Result<Vec<FetchResult>> {
-        let pb = ProgressBar::new(dependencies.len() as u64);
+        dependencies: &HashMap<String, Dependency>,
+        lock: &LockFile,
+        options: &FetchOptions,
+    ) -> Result<FetchReport> {
+        self.recover().await?;
+
+        let pb = ProgressBar::new(0);
         pb.set_style(
             ProgressStyle::default_bar()
                 .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} {msg}")
@@ -41,92 +403,1929 @@ impl Fetcher {
                 .progress_chars("#>-"),
         );
 
+        // 按名称排序，保证结果顺序稳定
+        let mut sorted: Vec<_> = dependencies.iter().collect();
+        sorted.sort_by(|a, b| a.0.cmp(b.0));
+        let mut pending: Vec<Pending> = sorted
+            .into_iter()
+            .map(|(name, dependency)| Pending {
+                name: name.clone(),
+                dependency: dependency.clone(),
+                parent: None,
+                manifest: None,
+            })
+            .collect();
+
         let mut results = Vec::new();
+        // 依赖图按来源去重：identity -> (包名, 声明的来源)
+        let mut seen: HashMap<String, (String, String)> = HashMap::new();
+        let mut edges: HashMap<String, Vec<String>> = HashMap::new();
+        let mut outputs: HashMap<String, Output> = HashMap::new();
+
+        while !pending.is_empty() {
+            let mut wave = Vec::new();
+            for node in std::mem::take(&mut pending) {
+                let source_id = node.dependency.source.to_string();
+                let identity = node.dependency.source.identity();
+
+                let Some(parent) = node.parent else {
+                    seen.entry(identity)
+                        .or_insert_with(|| (node.name.clone(), source_id));
+                    wave.push((node.name, node.dependency, None));
+                    continue;
+                };
+
+                let name = match seen.get(&identity) {
+                    Some((existing, existing_source)) if *existing_source == source_id => {
+                        existing.clone()
+                    }
+                    Some((existing, existing_source)) => {
+                        let name = format!("{}/{}", parent, node.name);
+                        let git_ref = node.dependency.source.git_ref();
+                        let path = self.output_path(&name, &node.dependency, git_ref);
+                        let error = anyhow::anyhow!(
+                            "Version conflict for {}: `{}` uses {}, but `{}` requires {}",
+                            identity,
+                            existing,
+                            existing_source,
+                            parent,
+                            source_id
+                        );
+                        results.push(FetchResult::failed(&name, path, error));
+                        continue;
+                    }
+                    None => {
+                        // 与已有的包重名时以父依赖名限定
+                        let name = if seen.values().any(|(n, _)| *n == node.name) {
+                            format!("{}/{}", parent, node.name)
+                        } else {
+                            node.name
+                        };
+                        seen.insert(identity, (name.clone(), source_id));
+                        wave.push((name.clone(), node.dependency, node.manifest));
+                        name
+                    }
+                };
+                edges.entry(parent).or_default().push(name);
+            }
+
+            pb.inc_length(wave.len() as u64);
+            let fetched = stream::iter(wave)
+                .map(|(name, dependency, manifest)| {
+                    let pb = pb.clone();
+                    async move {
+                        pb.set_message(format!("Downloading {}...", name));
+                        let (mut result, output) = self
+                            .fetch_one(&name, &dependency, lock.get(&name), options)
+                            .await;
+                        // 复现时不再读取上游清单，它声明的校验和记在锁定记录里
+                        if let (Some(package), Some(_)) = (&mut result.locked, &manifest) {
+                            package.sha256 = dependency.sha256.clone();
+                        }
+                        let children = match &result.locked {
+                            Some(package) => self
+                                .upstream_dependencies(&dependency, package, lock, manifest)
+                                .await
+                                .map(|children| (manifest_key(&dependency.source, package), children)),
+                            None => Ok((None, Vec::new())),
+                        };
+                        pb.inc(1);
+                        (result, output, children)
+                    }
+                })
+                .buffered(options.jobs.max(1))
+                .collect::<Vec<_>>()
+                .await;
+
+            for (result, output, children) in fetched {
+                if let Some(output) = output {
+                    outputs.insert(result.name.clone(), output);
+                }
+                match children {
+                    Ok((manifest, children)) => {
+                        pending.extend(children.into_iter().map(|(name, dependency)| Pending {
+                            name,
+                            dependency,
+                            parent: Some(result.name.clone()),
+                            manifest: manifest.clone(),
+                        }));
+                        results.push(result);
+                    }
+                    Err(e) => results.push(FetchResult::failed(
+                        &result.name,
+                        result.path,
+                        e.context("Failed to read upstream markdown.toml"),
+                    )),
+                }
+            }
+        }
+
+        // 在锁定记录中保存依赖图
+        for result in &mut results {
+            if let Some(locked) = &mut result.locked {
+                let mut children = edges.remove(&locked.name).unwrap_or_default();
+                children.sort();
+                children.dedup();
+                locked.dependencies = children;
+            }
+        }
+
+        // 写入任何文件之前，先检查所有目标路径是否安全且互不冲突
+        let output_dir = Path::new(&self.output_dir);
+        let previous = match ManagedFiles::load(output_dir)? {
+            Some(managed) => managed,
+            None => lock
+                .packages
+                .iter()
+                .flat_map(|p| p.files.iter().map(|f| f.path.clone()))
+                .collect(),
+        };
+        let mut plan = Plan::default();
+        let mut problems = Vec::new();
+        let mut claimed: HashMap<String, Vec<String>> = HashMap::new();
+        for result in &results {
+            let (Some(package), Some(output)) = (&result.locked, outputs.get(&result.name))
+            else {
+                continue;
+            };
+            let paths: Vec<&String> = match output {
+                Output::Symlink { destination, .. } => vec![destination],
+                Output::Files(_) | Output::Copy { .. } => {
+                    package.files.iter().map(|f| &f.path).collect()
+                }
+            };
+            for path in &paths {
+                if let Err(e) = plan.claim(&result.name, path) {
+                    problems.push(e.to_string());
+                    continue;
+                }
+                // 手写的文件不在记录中，覆盖后无法找回，除非指定 --force
+                if !options.force {
+                    for existing in self.unmanaged(path, &previous) {
+                        problems.push(format!(
+                            "`{}` would overwrite {}, which cardo did not write \
+                             (use --force to replace it)",
+                            result.name, existing
+                        ));
+                    }
+                }
+            }
+            claimed.insert(result.name.clone(), paths.into_iter().cloned().collect());
+        }
+        if !problems.is_empty() {
+            pb.finish_and_clear();
+            anyhow::bail!(
+                "Nothing was written because of conflicting or unsafe output paths:\n  {}",
+                problems.join("\n  ")
+            );
+        }
+
+        let failed = |results: &[FetchResult]| results.iter().any(|r| !r.success);
+        if options.all_or_nothing && failed(&results) {
+            pb.finish_and_clear();
+            return Ok(FetchReport {
+                results,
+                pruned: Vec::new(),
+                applied: false,
+            });
+        }
 
-        for (name, source) in dependencies {
-            pb.set_message(format!("Downloading {}...", name));
-            let result = self.fetch_one(name, source, force).await;
-            results.push(result);
-            pb.inc(1);
+        // 先把所有文件写到暂存目录，全部成功后再重命名到目标位置，
+        // 中途失败或中断都不会留下写了一半的文件
+        let staging = output_dir.join(format!("{}{}", STAGING_PREFIX, std::process::id()));
+        let mut staged = Vec::new();
+        for (id, result) in results.iter_mut().enumerate() {
+            let (Some(_), Some(output)) = (&result.locked, outputs.remove(&result.name)) else {
+                continue;
+            };
+            match self.stage_output(&staging, id, output, options).await {
+                Ok(files) => staged.push((id, files)),
+                Err(e) => *result = FetchResult::failed(&result.name, result.path.clone(), e),
+            }
+        }
+        if options.all_or_nothing && failed(&results) {
+            let _ = fs::remove_dir_all(&staging).await;
+            pb.finish_and_clear();
+            return Ok(FetchReport {
+                results,
+                pruned: Vec::new(),
+                applied: false,
+            });
         }
 
+        // 被替换的文件先移到备份目录，提交失败时据此撤销已做的改动
+        let mut journal = Journal::new(&self.output_dir, &staging);
+        for (id, files) in staged {
+            let mark = journal.entries.len();
+            if let Err(e) = self.commit(files, &mut journal).await {
+                self.rollback(&mut journal, mark).await?;
+                let result = &mut results[id];
+                *result = FetchResult::failed(&result.name, result.path.clone(), e);
+                if options.all_or_nothing {
+                    break;
+                }
+            }
+        }
+        if options.all_or_nothing && failed(&results) {
+            self.rollback(&mut journal, 0).await?;
+            let _ = fs::remove_dir_all(&staging).await;
+            pb.finish_and_clear();
+            return Ok(FetchReport {
+                results,
+                pruned: Vec::new(),
+                applied: false,
+            });
+        }
+
+        // 删除旧文件或保存记录失败时，连同已提交的文件一起撤销
+        let pruned = match self.record(&results, claimed, &mut journal, options).await {
+            Ok(pruned) => pruned,
+            Err(e) => {
+                self.rollback(&mut journal, 0).await?;
+                let _ = fs::remove_dir_all(&staging).await;
+                pb.finish_and_clear();
+                return Err(e);
+            }
+        };
+        // 日志删除后本次抓取即告完成，之后再中断也不会被撤销
+        let _ = fs::remove_file(&journal.path).await;
+        let _ = fs::remove_dir_all(&staging).await;
+
         pb.finish_with_message("Done!");
-        Ok(results)
+        Ok(FetchReport {
+            results,
+            pruned,
+            applied: true,
+        })
+    }
+
+    /// Once the new files are in place, prunes the old ones and records which files cardo
+    /// wrote. Returns what was pruned.
+    async fn record(
+        &self,
+        results: &[FetchResult],
+        mut claimed: HashMap<String, Vec<String>>,
+        journal: &mut Journal,
+        options: &FetchOptions,
+    ) -> Result<Vec<String>> {
+        let output_dir = Path::new(&self.output_dir);
+        let mut managed = ManagedFiles::load(output_dir)?.unwrap_or_default();
+        let pruned = if options.prune {
+            self.prune(&mut managed, results, &claimed, journal).await?
+        } else {
+            Vec::new()
+        };
+
+        // 记录写入的文件，clean 只删除这些文件
+        for result in results.iter().filter(|r| r.success) {
+            for path in claimed.remove(&result.name).unwrap_or_default() {
+                managed.insert(&path, &result.name);
+            }
+        }
+        managed
+            .save(output_dir)
+            .context("Failed to record the files written by cardo")?;
+        Ok(pruned)
+    }
+
+    /// Moves managed files that no dependency writes any more into the journal's backups.
+    /// Files of dependencies that failed this time are kept. Returns what was removed.
+    async fn prune(
+        &self,
+        managed: &mut ManagedFiles,
+        results: &[FetchResult],
+        claimed: &HashMap<String, Vec<String>>,
+        journal: &mut Journal,
+    ) -> Result<Vec<String>> {
+        let failed: HashSet<&str> = results
+            .iter()
+            .filter(|r| !r.success)
+            .map(|r| r.name.as_str())
+            .collect();
+        let wanted: HashSet<&str> = claimed.values().flatten().map(String::as_str).collect();
+
+        // 不知道由哪个依赖写入的文件，只在全部依赖都成功时删除
+        let stale: Vec<String> = managed
+            .iter()
+            .filter(|(path, owner)| {
+                !wanted.contains(path)
+                    && owner.map_or(failed.is_empty(), |owner| !failed.contains(owner))
+            })
+            .map(|(path, _)| path.to_string())
+            .collect();
+
+        let mut pruned = Vec::new();
+        for path in stale {
+            if self.removable(&path).await {
+                let output_path = format!("{}/{}", self.output_dir, path);
+                self.back_up(&output_path, journal).await?;
+                self.remove_empty_parents(&output_path).await;
+                pruned.push(output_path);
+            }
+            managed.remove(&path);
+        }
+        Ok(pruned)
+    }
+
+    /// Undoes fetches that were interrupted while replacing files, from the journal they
+    /// left in their staging directory, and removes what they staged.
+    async fn recover(&self) -> Result<()> {
+        // 命令执行期间持有项目锁，残留的暂存目录都来自已经结束的进程
+        let Ok(mut entries) = fs::read_dir(&self.output_dir).await else {
+            return Ok(());
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_name().to_string_lossy().starts_with(STAGING_PREFIX) {
+                continue;
+            }
+            let staging = entry.path();
+            if let Some(mut journal) = Journal::load(&self.output_dir, &staging).await {
+                self.rollback(&mut journal, 0).await?;
+            }
+            fs::remove_dir_all(&staging)
+                .await
+                .with_context(|| format!("Failed to remove {}", staging.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Files at or under `relative_path` in the output directory that `managed` does not
+    /// record, i.e. that writing `relative_path` would replace without cardo having written them.
+    fn unmanaged(&self, relative_path: &str, managed: &ManagedFiles) -> Vec<String> {
+        let output_path = Path::new(&self.output_dir).join(relative_path);
+        let Ok(metadata) = std::fs::symlink_metadata(&output_path) else {
+            return Vec::new();
+        };
+        if !metadata.is_dir() {
+            return if managed.covers(relative_path) {
+                Vec::new()
+            } else {
+                vec![relative_path.to_string()]
+            };
+        }
+        // 目录会被整体替换为符号链接，其中每个文件都要检查
+        utils::list_files(&output_path)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|path| path.strip_prefix(&self.output_dir).ok())
+            .map(|path| path.to_string_lossy().trim_start_matches('/').to_string())
+            .filter(|path| !managed.covers(path))
+            .collect()
+    }
+
+    /// Writes a dependency's output into `staging` without touching the output directory.
+    /// `id` keeps the staged names of different dependencies apart.
+    async fn stage_output(
+        &self,
+        staging: &Path,
+        id: usize,
+        output: Output,
+        options: &FetchOptions,
+    ) -> Result<Staged> {
+        fs::create_dir_all(staging)
+            .await
+            .with_context(|| format!("Failed to create directory {}", staging.display()))?;
+        let mut staged = Staged::default();
+
+        match output {
+            Output::Files(contents) => {
+                for (i, (file, content)) in contents.into_iter().enumerate() {
+                    // 内容未变化时不重写文件
+                    let output_path = format!("{}/{}", self.output_dir, file.path);
+                    if !options.force && fs::read(&output_path).await.is_ok_and(|c| c == content)
+                    {
+                        continue;
+                    }
+                    let temp = staging.join(format!("{}-{}", id, i));
+                    self.stage_file(&temp, &content).await?;
+                    staged.moves.push((temp, output_path));
+                }
+            }
+            Output::Copy {
+                destination,
+                contents,
+            } => {
+                staged.clear = Some((format!("{}/{}", self.output_dir, destination), false));
+                for (i, (file, content)) in contents.iter().enumerate() {
+                    let temp = staging.join(format!("{}-{}", id, i));
+                    self.stage_file(&temp, content).await?;
+                    staged
+                        .moves
+                        .push((temp, format!("{}/{}", self.output_dir, file.path)));
+                }
+            }
+            Output::Symlink {
+                destination,
+                target,
+                is_dir,
+            } => {
+                let destination = format!("{}/{}", self.output_dir, destination);
+                let temp = staging.join(id.to_string());
+                utils::symlink(&target, &temp, is_dir)
+                    .with_context(|| format!("Failed to create symlink {}", destination))?;
+                staged.clear = Some((destination.clone(), true));
+                staged.moves.push((temp, destination));
+            }
+        }
+        Ok(staged)
+    }
+
+    /// Moves staged files into the output directory, recording in `journal` what they replace.
+    async fn commit(&self, staged: Staged, journal: &mut Journal) -> Result<()> {
+        if let Some((destination, symlink)) = &staged.clear {
+            if self.needs_clearing(destination, *symlink).await {
+                self.back_up(destination, journal).await?;
+            }
+        }
+        for (temp, output_path) in &staged.moves {
+            self.create_parent(output_path).await?;
+            self.back_up(output_path, journal).await?;
+            fs::rename(temp, output_path)
+                .await
+                .with_context(|| format!("Failed to write file {}", output_path))?;
+        }
+        Ok(())
+    }
+
+    /// Moves whatever is at `output_path` into the journal's backup directory.
+    async fn back_up(&self, output_path: &str, journal: &mut Journal) -> Result<()> {
+        if fs::symlink_metadata(output_path).await.is_err() {
+            return journal.push(output_path, None).await;
+        }
+        fs::create_dir_all(&journal.backups)
+            .await
+            .with_context(|| format!("Failed to create directory {}", journal.backups.display()))?;
+        // 先写日志再移动，中断后下次运行总能找回
+        let backup = journal.backups.join(journal.entries.len().to_string());
+        journal.push(output_path, Some(backup.clone())).await?;
+        fs::rename(output_path, &backup)
+            .await
+            .with_context(|| format!("Failed to replace {}", output_path))
+    }
+
+    /// Undoes the journal entries after `mark`, newest first, restoring the backups.
+    async fn rollback(&self, journal: &mut Journal, mark: usize) -> Result<()> {
+        for (output_path, backup) in journal.entries.drain(mark..).rev() {
+            // 已记入日志但还没移走，原来的文件仍在原处
+            if let Some(backup) = &backup {
+                if fs::symlink_metadata(backup).await.is_err() {
+                    continue;
+                }
+            }
+            // 目录只可能是为新文件创建的，此时已经为空
+            match fs::symlink_metadata(&output_path).await {
+                Ok(metadata) if metadata.is_dir() => {
+                    let _ = fs::remove_dir(&output_path).await;
+                }
+                Ok(_) => fs::remove_file(&output_path).await?,
+                Err(_) => {}
+            }
+            self.remove_empty_parents(&output_path).await;
+            if let Some(backup) = backup {
+                self.create_parent(&output_path).await?;
+                fs::rename(&backup, &output_path).await.with_context(|| {
+                    format!(
+                        "Failed to restore {}; the old version is kept at {}",
+                        output_path,
+                        backup.display()
+                    )
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Dependencies declared by the markdown.toml at the root of a fetched repository.
+    /// `declared_in` is the manifest that declared this dependency, which is not read again.
+    async fn upstream_dependencies(
+        &self,
+        dependency: &Dependency,
+        package: &LockedPackage,
+        lock: &LockFile,
+        declared_in: Option<String>,
+    ) -> Result<Vec<(String, Dependency)>> {
+        // 从锁文件复现的包沿用记录的依赖，无需再读取上游清单
+        if let Some(locked) = lock.get(&package.name).filter(|l| {
+            l.source == package.source && l.commit == package.commit && l.files == package.files
+        }) {
+            return locked
+                .dependencies
+                .iter()
+                .filter_map(|child| lock.get(child))
+                .map(|child| {
+                    let mut dependency = Dependency::new(DependencySource::from_id(&child.source)?);
+                    dependency.sha256 = child.sha256.clone();
+                    Ok((child.name.clone(), dependency))
+                })
+                .collect();
+        }
+
+        let (Some(repository), Some(commit)) = (dependency.source.repository(), &package.commit)
+        else {
+            return Ok(Vec::new());
+        };
+        // 同一仓库同一提交中的文件共享一份清单，只由声明它们的依赖读取
+        if declared_in.is_some() && declared_in == manifest_key(&dependency.source, package) {
+            return Ok(Vec::new());
+        }
+
+        let url = dependency.source.raw_file_url(commit, "markdown.toml");
+        let content = match self.download(&url, true, None).await {
+            Ok(content) => content,
+            Err(e) if is_not_found(&e) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let declared = config::parse_upstream_dependencies(&String::from_utf8_lossy(&content))?;
+        let mut children: Vec<_> = declared.into_iter().collect();
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (name, child) in &mut children {
+            // 链接改写方式和输出位置由本项目决定，不沿用上游的设置，
+            // 否则上游可以把文件放到输出目录中任何未被占用的位置
+            child.links = LinkMode::default();
+            child.to = None;
+
+            // 上游清单不得让本机读取任意本地仓库
+            if let DependencySource::Git { url, .. } = &child.source {
+                if url.starts_with("file:") {
+                    anyhow::bail!(
+                        "`{}` in {} points at a local repository: {}",
+                        name,
+                        repository,
+                        url
+                    );
+                }
+            }
+
+            // 上游的本地路径依赖指向同一仓库的同一提交
+            if let DependencySource::Local { path, .. } = &child.source {
+                let path = path.trim_start_matches("./");
+                if path.starts_with('/') || path.split('/').any(|c| c == "..") {
+                    anyhow::bail!(
+                        "`{}` in {} points outside the repository: {}",
+                        name,
+                        repository,
+                        path
+                    );
+                }
+                if let Some(source) = dependency.source.in_same_repository(path, commit) {
+                    child.source = source;
+                }
+            }
+        }
+
+        Ok(children)
+    }
+
+    /// How `dependency`'s files map into the output directory.
+    fn placement<'a>(
+        &self,
+        name: &'a str,
+        dependency: &'a Dependency,
+        git_ref: Option<&'a str>,
+    ) -> Placement<'a> {
+        let source = &dependency.source;
+        let (root, main) = match source {
+            // 本地目录整体放在以目录名命名的位置
+            DependencySource::Local { path, .. } if self.project_dir.join(path).is_dir() => {
+                (source.file_name(), None)
+            }
+            _ => (source.root_dir(), source.main_path()),
+        };
+        Placement {
+            name,
+            dependency,
+            git_ref,
+            root,
+            main,
+        }
+    }
+
+    /// Location of a dependency as a whole relative to the output directory: its file,
+    /// or the directory holding its files.
+    fn root_path(&self, placement: &Placement) -> String {
+        let path = placement.main.as_ref().unwrap_or(&placement.root);
+        placement.output_path(&self.layout, path)
+    }
+
+    fn output_path(&self, name: &str, dependency: &Dependency, git_ref: Option<&str>) -> String {
+        let placement = self.placement(name, dependency, git_ref);
+        format!("{}/{}", self.output_dir, self.root_path(&placement))
     }
 
     async fn fetch_one(
         &self,
         name: &str,
-        source: &DependencySource,
-        force: bool,
-    ) -> FetchResult {
-        let output_path = format!("{}/{}", self.output_dir, source.output_path(name));
-
-        // 检查文件是否已存在
-        if !force && Path::new(&output_path).exists() {
-            return FetchResult {
-                name: name.to_string(),
-                path: output_path,
-                success: true,
-                error: None,
-            };
+        dependency: &Dependency,
+        locked: Option<&LockedPackage>,
+        options: &FetchOptions,
+    ) -> (FetchResult, Option<Output>) {
+        let source = &dependency.source;
+        let mut warnings = Vec::new();
+        let result = match source {
+            DependencySource::Local { path, symlink } => {
+                let expected = locked.filter(|_| options.locked);
+                self.fetch_local(name, dependency, path, *symlink, expected)
+                    .await
+            }
+            _ => self
+                .fetch_remote(name, dependency, locked, options, &mut warnings)
+                .await
+                .map(|(package, contents)| rewrite_contents(dependency, package, contents)),
+        };
+
+        match result {
+            Ok((locked, output)) => {
+                let output_path = self.output_path(name, dependency, locked.git_ref.as_deref());
+                let mut result = FetchResult::ok(name, output_path, locked);
+                result.warnings = warnings;
+                (result, Some(output))
+            }
+            Err(e) => {
+                let output_path = self.output_path(name, dependency, source.git_ref());
+                (FetchResult::failed(name, output_path, e), None)
+            }
         }
+    }
 
-        // 创建目录
-        if let Some(parent) = Path::new(&output_path).parent() {
-            if let Err(e) = fs::create_dir_all(parent).await {
-                return FetchResult {
-                    name: name.to_string(),
-                    path: output_path,
-                    success: false,
-                    error: Some(format!("Failed to create directory: {}", e)),
+    /// Pins and downloads a remote dependency. Returns the lock entry together with the
+    /// content of every file that has to be (re)written.
+    async fn fetch_remote(
+        &self,
+        name: &str,
+        dependency: &Dependency,
+        locked: Option<&LockedPackage>,
+        options: &FetchOptions,
+        warnings: &mut Vec<String>,
+    ) -> Result<(LockedPackage, Vec<(LockedFile, Vec<u8>)>)> {
+        let source = &dependency.source;
+        let source_id = source.to_string();
+        self.authorize(source)?;
+
+        // 锁文件中的记录只有在依赖声明未改变时才有效
+        if let Some(locked) = locked.filter(|l| l.source == source_id) {
+            check_integrity(dependency, locked)?;
+            let locked = self.relocate(name, dependency, locked);
+            let contents: Vec<_> = stream::iter(&locked.files)
+                .map(|file| self.reproduce(file, rewrites(dependency, file), options))
+                .buffered(options.jobs.max(1))
+                .try_collect()
+                .await?;
+            return Ok((locked, contents.into_iter().flatten().collect()));
+        }
+        if options.offline {
+            return Err(FetchError::NotLocked(name.to_string()).into());
+        }
+
+        let precise = options.precise.get(name).map(String::as_str);
+        let resolved = self.resolve(source, precise).await?;
+        let placement = self.placement(name, dependency, resolved.git_ref.as_deref());
+
+        // 目录和 glob 依赖在解析出的提交上展开为文件列表
+        let targets = match (source, &resolved.commit) {
+            (
+                DependencySource::GitHub { path, .. }
+                | DependencySource::GitLab { path, .. }
+                | DependencySource::Gitea { path, .. }
+                | DependencySource::Bitbucket { path, .. }
+                | DependencySource::Gist { file: path, .. }
+                | DependencySource::Git { path, .. },
+                Some(commit),
+            ) if source.is_tree() =>
+            {
+                let tree = self.list_files(source, commit).await?;
+                let selected = source.select_files(&tree)?;
+                if selected.is_empty() {
+                    anyhow::bail!(
+                        "No files in {} at {} match `{}`",
+                        source.repository().unwrap_or_default(),
+                        commit,
+                        path
+                    );
+                }
+                selected
+                    .iter()
+                    .map(|p| {
+                        let path = placement.output_path(&self.layout, p);
+                        (path, source.raw_file_url(commit, p))
+                    })
+                    .collect()
+            }
+            _ => vec![(self.root_path(&placement), resolved.url.clone())],
+        };
+
+        // 下载文件；固定到提交的 URL 内容不会变化，可以直接使用缓存
+        let immutable = resolved.commit.is_some();
+        let mut downloads: Vec<(LockedFile, Vec<u8>)> = stream::iter(targets)
+            .map(|(path, url)| async move {
+                let content = self.download(&url, immutable, None).await?;
+                let file = LockedFile {
+                    path,
+                    url,
+                    sha256: utils::sha256_hex(&content),
+                    rewritten: None,
                 };
+                Ok::<_, anyhow::Error>((file, content))
+            })
+            .buffered(options.jobs.max(1))
+            .try_collect()
+            .await?;
+
+        // 沿相对链接抓取同一提交中被引用的文档和资源文件
+        if let Some(commit) = &resolved.commit {
+            if source.follow_links().is_some() || source.fetches_assets() {
+                self.follow_links(&placement, commit, &mut downloads, options, warnings)
+                    .await?;
             }
         }
 
-        // 下载文件
-        match self.client.fetch_file_with_retry(source, 3).await {
-            Ok(content) => {
-                match fs::File::create(&output_path).await {
-                    Ok(mut file) => {
-                        if let Err(e) = file.write_all(content.as_bytes()).await {
-                            FetchResult {
-                                name: name.to_string(),
-                                path: output_path,
-                                success: false,
-                                error: Some(format!("Failed to write file: {}", e)),
-                            }
+        let package = LockedPackage {
+            name: name.to_string(),
+            source: source_id,
+            git_ref: resolved.git_ref,
+            commit: resolved.commit,
+            files: downloads.iter().map(|(file, _)| file.clone()).collect(),
+            links: dependency.links,
+            dependencies: Vec::new(),
+            sha256: None,
+        };
+
+        // 校验通过后才写入文件
+        check_integrity(dependency, &package)?;
+        Ok((package, downloads))
+    }
+
+    /// Fetches files linked from the Markdown in `downloads` at the same commit: documents
+    /// up to the `follow-links` depth and, with `assets = true`, everything else they reference.
+    async fn follow_links(
+        &self,
+        placement: &Placement<'_>,
+        commit: &str,
+        downloads: &mut Vec<(LockedFile, Vec<u8>)>,
+        options: &FetchOptions,
+        warnings: &mut Vec<String>,
+    ) -> Result<()> {
+        let source = &placement.dependency.source;
+        let mut known: HashSet<String> = downloads.iter().map(|(f, _)| f.url.clone()).collect();
+        let mut remaining = source.follow_links().unwrap_or(0);
+        let mut level = 0..downloads.len();
+
+        loop {
+            let mut documents = Vec::new();
+            let mut assets = Vec::new();
+            for (file, content) in &downloads[level.clone()] {
+                let Some(base) = source.repo_file_path(commit, &file.url) else {
+                    continue;
+                };
+                if !links::is_markdown(&base) {
+                    continue;
+                }
+                for link in links::relative_links(&String::from_utf8_lossy(content)) {
+                    let Some(target) = links::resolve(&base, &link) else {
+                        continue;
+                    };
+                    let url = source.raw_file_url(commit, &target);
+                    let wanted = if links::is_markdown(&target) {
+                        remaining > 0
+                    } else {
+                        source.fetches_assets()
+                    };
+                    if wanted && known.insert(url.clone()) {
+                        let path = placement.output_path(&self.layout, &target);
+                        if links::is_markdown(&target) {
+                            documents.push((path, url));
                         } else {
-                            FetchResult {
-                                name: name.to_string(),
-                                path: output_path,
-                                success: true,
-                                error: None,
-                            }
+                            assets.push((path, url));
                         }
                     }
-                    Err(e) => FetchResult {
-                        name: name.to_string(),
-                        path: output_path,
-                        success: false,
-                        error: Some(format!("Failed to create file: {}", e)),
-                    },
                 }
             }
-            Err(e) => FetchResult {
-                name: name.to_string(),
-                path: output_path,
-                success: false,
-                error: Some(format!("{}", e)),
-            },
+
+            let start = downloads.len();
+            downloads.extend(
+                self.download_linked(documents, None, options, warnings)
+                    .await?,
+            );
+            downloads.extend(
+                self.download_linked(assets, Some(options.max_asset_size), options, warnings)
+                    .await?,
+            );
+            if downloads.len() == start {
+                return Ok(());
+            }
+            level = start..downloads.len();
+            remaining = remaining.saturating_sub(1);
         }
     }
 
-    pub async fn clean(&self) -> Result<()> {
-        if Path::new(&self.output_dir).exists() {
-            fs::remove_dir_all(&self.output_dir).await?;
-            fs::create_dir_all(&self.output_dir).await?;
+    /// Downloads link targets, skipping links that are already broken upstream and,
+    /// with a warning, files over `limit`.
+    async fn download_linked(
+        &self,
+        targets: Vec<(String, String)>,
+        limit: Option<u64>,
+        options: &FetchOptions,
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<(LockedFile, Vec<u8>)>> {
+        let linked: Vec<_> = stream::iter(targets)
+            .map(|(path, url)| async move {
+                match self.download(&url, true, limit).await {
+                    Ok(content) => {
+                        let sha256 = utils::sha256_hex(&content);
+                        let file = LockedFile {
+                            path,
+                            url,
+                            sha256,
+                            rewritten: None,
+                        };
+                        Ok(Ok((file, content)))
+                    }
+                    // 上游本身失效的链接不影响其他文件
+                    Err(e) if is_not_found(&e) => Ok(Err(None)),
+                    // 过大的文件不下载，链接仍指向上游
+                    Err(e) if matches!(e.downcast_ref(), Some(GitHubError::TooLarge(..))) => {
+                        Ok(Err(Some(format!("skipped a linked file: {:#}", e))))
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .buffered(options.jobs.max(1))
+            .try_collect()
+            .await?;
+
+        let mut files = Vec::new();
+        for item in linked {
+            match item {
+                Ok(file) => files.push(file),
+                Err(warning) => warnings.extend(warning),
+            }
+        }
+        Ok(files)
+    }
+
+    /// Moves locked files to where the current layout puts them, so that changing
+    /// `out-dir`, `layout` or `to` does not require re-resolving.
+    fn relocate(&self, name: &str, dependency: &Dependency, locked: &LockedPackage) -> LockedPackage {
+        let source = &dependency.source;
+        let placement = self.placement(name, dependency, locked.git_ref.as_deref());
+
+        let mut relocated = locked.clone();
+        for file in &mut relocated.files {
+            let path = match (&locked.commit, source.repository()) {
+                (Some(commit), Some(_)) => source.repo_file_path(commit, &file.url),
+                _ => placement.main.clone(),
+            };
+            if let Some(path) = path {
+                file.path = placement.output_path(&self.layout, &path);
+            }
+        }
+        // 改写后的链接取决于各文件的位置和 links，两者变化后记录的哈希不再可信
+        let moved = relocated.files.iter().zip(&locked.files).any(|(a, b)| a.path != b.path);
+        if moved || locked.links != dependency.links {
+            relocated.links = dependency.links;
+            for file in &mut relocated.files {
+                file.rewritten = None;
+            }
+        }
+        relocated
+    }
+
+    /// Loads the locked content of a file, or `None` if the copy on disk already matches.
+    /// Files whose links get rewritten are compared with the hash recorded after rewriting.
+    async fn reproduce(
+        &self,
+        file: &LockedFile,
+        rewritten: bool,
+        options: &FetchOptions,
+    ) -> Result<Option<(LockedFile, Vec<u8>)>> {
+        let output_path = format!("{}/{}", self.output_dir, file.path);
+
+        // 文件已存在且与上次写入的内容一致时跳过下载
+        if !options.force {
+            let expected = match &file.rewritten {
+                Some(sha256) if rewritten => sha256,
+                _ => &file.sha256,
+            };
+            if let Ok(existing) = fs::read(&output_path).await {
+                if utils::sha256_hex(&existing) == *expected {
+                    return Ok(None);
+                }
+            }
+        }
+
+        // 锁文件记录了内容哈希，缓存命中时无需访问网络
+        let cached = self.cache.as_ref().and_then(|c| c.get(&file.sha256));
+        let content = match cached {
+            Some(content) => content,
+            None if options.offline => return Err(FetchError::NotCached(file.url.clone()).into()),
+            None => self.download(&file.url, false, None).await?,
+        };
+        let sha256 = utils::sha256_hex(&content);
+        if sha256 != file.sha256 {
+            anyhow::bail!(
+                "Checksum mismatch for {}: markdown.lock expects {}, got {}",
+                file.path,
+                file.sha256,
+                sha256
+            );
+        }
+
+        Ok(Some((file.clone(), content)))
+    }
+
+    /// Downloads `url`, consulting the shared cache first when the URL is commit-pinned.
+    /// Content larger than `limit` bytes is rejected.
+    async fn download(&self, url: &str, immutable: bool, limit: Option<u64>) -> Result<Vec<u8>> {
+        if let Some(cache) = &self.cache {
+            if let Some(content) = immutable
+                .then(|| cache.lookup(url))
+                .flatten()
+                .and_then(|sha256| cache.get(&sha256))
+            {
+                if let Some(limit) = limit.filter(|limit| content.len() as u64 > *limit) {
+                    return Err(GitHubError::TooLarge(url.to_string(), limit).into());
+                }
+                return Ok(content);
+            }
+        }
+
+        let content = if git::parse_locator(url).is_some() {
+            let content = self.git.read_file(url).await?;
+            if let Some(limit) = limit.filter(|limit| content.len() as u64 > *limit) {
+                return Err(GitHubError::TooLarge(url.to_string(), limit).into());
+            }
+            content
+        } else {
+            self.client.fetch_url_with_retry(url, 3, limit).await?
+        };
+        if let Some(cache) = &self.cache {
+            // 缓存写入失败不影响本次下载
+            let _ = cache.put(&content, immutable.then_some(url));
+        }
+        Ok(content)
+    }
+
+    async fn fetch_local(
+        &self,
+        name: &str,
+        dependency: &Dependency,
+        path: &str,
+        symlink: bool,
+        expected: Option<&LockedPackage>,
+    ) -> Result<(LockedPackage, Output)> {
+        let source_path = self.project_dir.join(path);
+        let metadata = fs::metadata(&source_path)
+            .await
+            .with_context(|| format!("Local path not found: {}", source_path.display()))?;
+
+        let placement = self.placement(name, dependency, None);
+        let mut contents = Vec::new();
+        if metadata.is_dir() {
+            for file in utils::list_files(&source_path)? {
+                let relative = file
+                    .strip_prefix(&source_path)?
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                let content = fs::read(&file).await?;
+                let locked = LockedFile {
+                    path: placement.output_path(
+                        &self.layout,
+                        &format!("{}/{}", placement.root, relative),
+                    ),
+                    url: format!("{}/{}", path.trim_end_matches('/'), relative),
+                    sha256: utils::sha256_hex(&content),
+                    rewritten: None,
+                };
+                contents.push((locked, content));
+            }
+        } else {
+            let content = fs::read(&source_path).await?;
+            let locked = LockedFile {
+                path: self.root_path(&placement),
+                url: path.to_string(),
+                sha256: utils::sha256_hex(&content),
+                rewritten: None,
+            };
+            contents.push((locked, content));
+        }
+
+        let package = LockedPackage {
+            name: name.to_string(),
+            source: dependency.source.to_string(),
+            git_ref: None,
+            commit: None,
+            files: contents.iter().map(|(file, _)| file.clone()).collect(),
+            links: LinkMode::default(),
+            dependencies: Vec::new(),
+            sha256: None,
+        };
+        check_integrity(dependency, &package)?;
+        // 本地文件的内容变化同样需要更新锁文件
+        if expected.is_some_and(|locked| locked.files != package.files) {
+            return Err(FetchError::LockMismatch(name.to_string()).into());
+        }
+
+        let destination = self.root_path(&placement);
+        let output = if symlink {
+            Output::Symlink {
+                destination,
+                target: fs::canonicalize(&source_path).await?,
+                is_dir: metadata.is_dir(),
+            }
+        } else {
+            Output::Copy {
+                destination,
+                contents,
+            }
+        };
+        Ok((package, output))
+    }
+
+    /// Writes `content` to a staging file, hardlinking it from the cache when possible.
+    /// Renaming the staged file over the destination later never modifies an old file in
+    /// place, which could be a hardlink into the cache.
+    async fn stage_file(&self, temp: &Path, content: &[u8]) -> Result<()> {
+        if let Some(cache) = &self.cache {
+            let sha256 = utils::sha256_hex(content);
+            if cache.install(&sha256, temp).is_ok() {
+                return Ok(());
+            }
+        }
+
+        fs::write(temp, content)
+            .await
+            .with_context(|| format!("Failed to write file {}", temp.display()))
+    }
+
+    async fn create_parent(&self, output_path: &str) -> Result<()> {
+        // 创建目录
+        if let Some(parent) = Path::new(output_path).parent() {
+            fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
         }
         Ok(())
     }
+
+    /// Whether the destination of a local dependency has to be moved aside first. Symlinks
+    /// always are; copies only when they are about to be replaced by a symlink.
+    async fn needs_clearing(&self, output_path: &str, symlink: bool) -> bool {
+        // 旧的符号链接可能指向源文件，必须先移走，避免写穿到源目录
+        fs::symlink_metadata(output_path)
+            .await
+            .is_ok_and(|metadata| symlink || metadata.file_type().is_symlink())
+    }
+
+    /// Pins a dependency to a concrete commit. `precise` overrides the ref from the manifest.
+    async fn resolve(
+        &self,
+        source: &DependencySource,
+        precise: Option<&str>,
+    ) -> Result<Resolved> {
+        match source {
+            DependencySource::Gist { id, .. } => {
+                let rev = precise.or_else(|| source.git_ref());
+                // 完整的修订 SHA 无需再查询；否则在修订历史中查找
+                let revision = match rev {
+                    Some(rev) if utils::is_full_sha(rev) => rev.to_string(),
+                    _ => gist::resolve_revision(&self.client, id, rev).await?,
+                };
+                Ok(Resolved {
+                    url: source.raw_url_at(&revision),
+                    git_ref: rev.map(str::to_string),
+                    commit: Some(revision),
+                })
+            }
+            DependencySource::Url(url) | DependencySource::Local { path: url, .. } => Ok(Resolved {
+                git_ref: None,
+                commit: None,
+                url: url.clone(),
+            }),
+            _ => {
+                let git_ref = match precise.or_else(|| source.git_ref()) {
+                    Some(git_ref) => git_ref.to_string(),
+                    None => self.default_branch(source).await?,
+                };
+                let commit = self.resolve_commit(source, &git_ref).await?;
+                Ok(Resolved {
+                    url: source.raw_url_at(&commit),
+                    git_ref: Some(git_ref),
+                    commit: Some(commit),
+                })
+            }
+        }
+    }
+
+    /// Branch used when a repository dependency names no tag, branch or revision.
+    async fn default_branch(&self, source: &DependencySource) -> Result<String> {
+        let client = &self.client;
+        Ok(match source {
+            DependencySource::GitHub { owner, repo, .. } => {
+                client.default_branch(owner, repo).await?
+            }
+            DependencySource::GitLab { host, project, .. } => {
+                gitlab::default_branch(client, host, project).await?
+            }
+            DependencySource::Gitea {
+                host, owner, repo, ..
+            } => gitea::default_branch(client, host, owner, repo).await?,
+            DependencySource::Bitbucket {
+                host, owner, repo, ..
+            } => bitbucket::default_branch(client, host, owner, repo).await?,
+            DependencySource::Git { url, .. } => self.git.default_branch(url).await?,
+            _ => anyhow::bail!("{} is not a repository", source),
+        })
+    }
+
+    /// Full commit SHA of `git_ref` in the source's repository.
+    async fn resolve_commit(&self, source: &DependencySource, git_ref: &str) -> Result<String> {
+        let client = &self.client;
+        Ok(match source {
+            // 克隆仓库时总要取回提交，完整的 SHA 也交给 git
+            DependencySource::Git { url, .. } => self.git.resolve_commit(url, git_ref).await?,
+            // 完整的提交 SHA 无需再向 API 查询
+            _ if utils::is_full_sha(git_ref) => git_ref.to_string(),
+            DependencySource::GitHub { owner, repo, .. } => {
+                client.resolve_commit(owner, repo, git_ref).await?
+            }
+            DependencySource::GitLab { host, project, .. } => {
+                gitlab::resolve_commit(client, host, project, git_ref).await?
+            }
+            DependencySource::Gitea {
+                host, owner, repo, ..
+            } => gitea::resolve_commit(client, host, owner, repo, git_ref).await?,
+            DependencySource::Bitbucket {
+                host, owner, repo, ..
+            } => bitbucket::resolve_commit(client, host, owner, repo, git_ref).await?,
+            _ => anyhow::bail!("{} is not a repository", source),
+        })
+    }
+
+    /// Every file in the source's repository at `commit`.
+    async fn list_files(&self, source: &DependencySource, commit: &str) -> Result<Vec<String>> {
+        match source {
+            DependencySource::GitHub { owner, repo, .. } => {
+                Ok(self.client.list_tree(owner, repo, commit).await?)
+            }
+            DependencySource::GitLab { host, project, .. } => {
+                Ok(gitlab::list_tree(&self.client, host, project, commit).await?)
+            }
+            DependencySource::Gitea {
+                host, owner, repo, ..
+            } => Ok(gitea::list_tree(&self.client, host, owner, repo, commit).await?),
+            DependencySource::Bitbucket {
+                host, owner, repo, ..
+            } => {
+                let root = source.root_dir();
+                Ok(bitbucket::list_tree(&self.client, host, owner, repo, commit, &root).await?)
+            }
+            DependencySource::Gist { id, .. } => Ok(gist::list_files(&self.client, id, commit).await?),
+            DependencySource::Git { url, .. } => Ok(self.git.list_files(url, commit).await?),
+            DependencySource::Url(_) | DependencySource::Local { .. } => Ok(Vec::new()),
+        }
+    }
+
+    /// Deletes the files a dependency wrote into the output directory and returns their paths.
+    pub async fn remove(
+        &self,
+        name: &str,
+        dependency: &Dependency,
+        locked: Option<&LockedPackage>,
+    ) -> Result<Vec<String>> {
+        let git_ref = locked.and_then(|l| l.git_ref.as_deref());
+        let placement = self.placement(name, dependency, git_ref);
+        let root = self.root_path(&placement);
+
+        // 符号链接只删除链接本身，不能删除其指向的源文件
+        let is_symlink = fs::symlink_metadata(format!("{}/{}", self.output_dir, root))
+            .await
+            .is_ok_and(|metadata| metadata.file_type().is_symlink());
+        let relative_paths = match locked {
+            _ if is_symlink => vec![root],
+            Some(locked) => locked.files.iter().map(|f| f.path.clone()).collect(),
+            None => vec![root],
+        };
+
+        let output_dir = Path::new(&self.output_dir);
+        let mut managed = ManagedFiles::load(output_dir)?;
+        let mut removed = Vec::new();
+        for relative_path in relative_paths {
+            if self.remove_file(&relative_path).await? {
+                removed.push(format!("{}/{}", self.output_dir, relative_path));
+            }
+            if let Some(managed) = &mut managed {
+                managed.remove(&relative_path);
+            }
+        }
+        if let Some(managed) = managed {
+            managed.save(output_dir)?;
+        }
+        Ok(removed)
+    }
+
+    /// Deletes a file or symlink written by cardo and the directories it leaves empty.
+    /// Returns `false` if there was nothing to delete.
+    async fn remove_file(&self, relative_path: &str) -> Result<bool> {
+        if !self.removable(relative_path).await {
+            return Ok(false);
+        }
+        let output_path = format!("{}/{}", self.output_dir, relative_path);
+        fs::remove_file(&output_path).await?;
+        self.remove_empty_parents(&output_path).await;
+        Ok(true)
+    }
+
+    /// Whether `relative_path` is a file or symlink that can be deleted without reaching
+    /// outside the output directory.
+    async fn removable(&self, relative_path: &str) -> bool {
+        // 锁文件或记录可能被手工改过，不删除输出目录之外的文件
+        if !plan::is_safe(relative_path) {
+            return false;
+        }
+        // 也不能经由符号链接删除其指向的源文件
+        for (i, _) in relative_path.match_indices('/') {
+            let dir = format!("{}/{}", self.output_dir, &relative_path[..i]);
+            if fs::symlink_metadata(&dir)
+                .await
+                .is_ok_and(|metadata| metadata.file_type().is_symlink())
+            {
+                return false;
+            }
+        }
+        let output_path = format!("{}/{}", self.output_dir, relative_path);
+        fs::symlink_metadata(&output_path)
+            .await
+            .is_ok_and(|metadata| !metadata.is_dir())
+    }
+
+    /// Removes now-empty directories between `output_path` and the output directory.
+    async fn remove_empty_parents(&self, output_path: &str) {
+        let root = Path::new(&self.output_dir);
+        let mut current = Path::new(output_path).parent();
+        while let Some(dir) = current {
+            if dir == root || !dir.starts_with(root) || fs::remove_dir(dir).await.is_err() {
+                break;
+            }
+            current = dir.parent();
+        }
+    }
+
+    /// Deletes the files cardo wrote into the output directory, leaving any others in place.
+    /// Without a `.cardo-manifest` record, the files in `lock` are taken as cardo's.
+    /// `all` empties the whole directory instead; `dry_run` only reports what would happen.
+    pub async fn clean(&self, lock: &LockFile, all: bool, dry_run: bool) -> Result<CleanReport> {
+        let output_dir = Path::new(&self.output_dir);
+        if !output_dir.exists() {
+            return Ok(CleanReport::default());
+        }
+        let present: Vec<String> = utils::list_files(output_dir)?
+            .iter()
+            .filter_map(|file| file.strip_prefix(output_dir).ok())
+            .map(|file| file.to_string_lossy().replace('\\', "/"))
+            .collect();
+        let full_path = |path: &str| format!("{}/{}", self.output_dir, path);
+
+        if all {
+            if !dry_run {
+                fs::remove_dir_all(output_dir).await?;
+                fs::create_dir_all(output_dir).await?;
+            }
+            return Ok(CleanReport {
+                removed: present.iter().map(|p| full_path(p)).collect(),
+                kept: Vec::new(),
+            });
+        }
+
+        let mut managed = match ManagedFiles::load(output_dir)? {
+            Some(managed) => managed,
+            None => lock
+                .packages
+                .iter()
+                .flat_map(|p| p.files.iter().map(|f| f.path.clone()))
+                .collect(),
+        };
+
+        let mut report = CleanReport {
+            removed: Vec::new(),
+            kept: present
+                .iter()
+                .filter(|path| !managed.covers(path))
+                .map(|path| full_path(path))
+                .collect(),
+        };
+        let paths: Vec<String> = managed.iter().map(|(path, _)| path.to_string()).collect();
+        for path in paths {
+            let removed = if dry_run {
+                self.removable(&path).await
+            } else {
+                self.remove_file(&path).await?
+            };
+            if removed {
+                report.removed.push(full_path(&path));
+                managed.remove(&path);
+            } else if plan::is_safe(&path) && fs::symlink_metadata(full_path(&path)).await.is_err() {
+                managed.remove(&path);
+            }
+        }
+
+        // 没能删除的文件仍记录为 cardo 写入的，下次清理时再处理
+        if !dry_run {
+            managed.save(output_dir)?;
+        }
+        Ok(report)
+    }
+}
+
+/// Whether `file` goes through link rewriting when written.
+fn rewrites(dependency: &Dependency, file: &LockedFile) -> bool {
+    dependency.links != LinkMode::Keep && links::is_markdown(&file.path)
+}
+
+/// Rewrites the links of the Markdown among `contents`, recording in `package` the hash of
+/// every file the rewriting changed.
+fn rewrite_contents(
+    dependency: &Dependency,
+    mut package: LockedPackage,
+    contents: Vec<(LockedFile, Vec<u8>)>,
+) -> (LockedPackage, Output) {
+    let contents: Vec<_> = contents
+        .into_iter()
+        .map(|(mut file, content)| {
+            if !rewrites(dependency, &file) {
+                return (file, content);
+            }
+            let content = rewrite_links(dependency, &package, &file, &content).unwrap_or(content);
+            let sha256 = utils::sha256_hex(&content);
+            file.rewritten = (sha256 != file.sha256).then_some(sha256);
+            (file, content)
+        })
+        .collect();
+    for (file, _) in &contents {
+        if let Some(locked) = package.files.iter_mut().find(|f| f.path == file.path) {
+            locked.rewritten = file.rewritten.clone();
+        }
+    }
+    (package, Output::Files(contents))
+}
+
+/// Rewrites the relative links of a fetched Markdown file: to the fetched copy when there is
+/// one (`links = "local"`), otherwise to the upstream file pinned to the resolved commit.
+fn rewrite_links(
+    dependency: &Dependency,
+    package: &LockedPackage,
+    file: &LockedFile,
+    content: &[u8],
+) -> Option<Vec<u8>> {
+    let markdown = std::str::from_utf8(content).ok()?;
+    let rewritten = match (&dependency.source, &package.commit) {
+        (source, Some(commit)) => {
+            let base = source.repo_file_path(commit, &file.url)?;
+            // 仓库内路径 -> 输出目录中的路径
+            let fetched: HashMap<String, &str> = package
+                .files
+                .iter()
+                .filter_map(|f| Some((source.repo_file_path(commit, &f.url)?, f.path.as_str())))
+                .collect();
+
+            links::rewrite(markdown, |link| {
+                let (path, suffix) = links::split_suffix(link);
+                let target = links::resolve(&base, path)?;
+                match fetched.get(target.as_str()) {
+                    Some(local) if dependency.links == LinkMode::Local => {
+                        Some(format!("{}{}", links::relative_path(&file.path, local), suffix))
+                    }
+                    // 没有网页地址的仓库保留原链接
+                    _ => source
+                        .web_url(commit, &target)
+                        .map(|url| format!("{}{}", url, suffix)),
+                }
+            })
+        }
+        (DependencySource::Url(url), _) => {
+            let base = reqwest::Url::parse(url).ok()?;
+            links::rewrite(markdown, |link| base.join(link).ok().map(String::from))
+        }
+        _ => return None,
+    };
+    Some(rewritten.into_bytes())
+}
+
+/// Identifies the manifest of the repository and commit a package was fetched from.
+fn manifest_key(source: &DependencySource, package: &LockedPackage) -> Option<String> {
+    match (source.repository(), &package.commit) {
+        (Some(repository), Some(commit)) => Some(format!("{}@{}", repository, commit)),
+        _ => None,
+    }
+}
+
+/// Whether a download failed because the file does not exist upstream.
+fn is_not_found(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref(), Some(GitHubError::NotFound(_)))
+        || matches!(error.downcast_ref(), Some(GitError::NotFound(_)))
+}
+
+/// Compares the content digest against the `sha256`/`integrity` declared in markdown.toml.
+fn check_integrity(dependency: &Dependency, package: &LockedPackage) -> Result<()> {
+    if let Some(expected) = &dependency.sha256 {
+        let actual = package.digest();
+        if &actual != expected {
+            anyhow::bail!(
+                "Integrity check failed: markdown.toml expects sha256 {}, got {}",
+                expected,
+                actual
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(spec: &str) -> DependencySource {
+        let value: toml::Value = toml::from_str(&format!("dep = {}", spec)).unwrap();
+        DependencySource::parse(&value["dep"]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_failure_kind() {
+        let kind = |error: FetchError| FailureKind::of(&anyhow::Error::new(error).context("x"));
+        assert_eq!(kind(FetchError::LockMismatch("a".to_string())), FailureKind::LockOutdated);
+        assert_eq!(kind(FetchError::NotLocked("a".to_string())), FailureKind::LockOutdated);
+        assert_eq!(kind(FetchError::NotCached("a.md".to_string())), FailureKind::Network);
+
+        let routes = [("/broken".to_string(), "<html>".to_string())];
+        let base = crate::testing::serve(&routes).await;
+        let client = GitHubClient::new(None);
+        let kind = |error: GitHubError| FailureKind::of(&error.into());
+        let decode = client.get_json::<String>(&format!("{}/broken", base), "x").await;
+        assert_eq!(kind(decode.unwrap_err()), FailureKind::Other);
+        let unavailable = client.get_json::<String>("http://127.0.0.1:1/x", "x").await;
+        assert_eq!(kind(unavailable.unwrap_err()), FailureKind::Network);
+        assert_eq!(kind(GitHubError::NotFound("x".to_string())), FailureKind::Other);
+    }
+
+    #[test]
+    fn test_gitlab_token_scope() {
+        let tokens = ForgeTokens {
+            gitlab: Some("env".to_string()),
+            hosts: HashMap::from([("https://gl.corp".to_string(), "own".to_string())]),
+            declared: HashSet::from([
+                "https://gitlab.mine".to_string(),
+                "http://gitlab.local".to_string(),
+            ]),
+            ..Default::default()
+        };
+        let fetcher = Fetcher::new("out".to_string(), PathBuf::from("."), Some("gh".to_string()))
+            .with_tokens(tokens);
+        for host in [
+            "https://gitlab.com",
+            "https://gitlab.mine",
+            "https://gl.corp",
+            "https://attacker.example",
+        ] {
+            let spec = format!(r#"{{ git = "gitlab:g/p/a.md", host = "{}" }}"#, host);
+            fetcher.authorize(&source(&spec)).unwrap();
+        }
+
+        let header = |url: &str| fetcher.client.credential(url);
+        let env = Some(("PRIVATE-TOKEN", "env".to_string()));
+        assert_eq!(header("https://gitlab.com/api/v4/projects/g%2Fp"), env);
+        assert_eq!(header("https://gitlab.mine/api/v4/projects/g%2Fp"), env);
+        assert_eq!(
+            header("https://gl.corp/api/v4/projects/g%2Fp"),
+            Some(("PRIVATE-TOKEN", "own".to_string()))
+        );
+        // 上游清单中出现的主机拿不到环境变量中的 token
+        assert_eq!(header("https://attacker.example/api/v4/projects/g%2Fp"), None);
+        assert_eq!(
+            header("https://api.github.com/repos/o/r"),
+            Some(("Authorization", "token gh".to_string()))
+        );
+
+        let plain = source(r#"{ git = "gitlab:g/p/a.md", host = "http://gitlab.local" }"#);
+        assert!(fetcher.authorize(&plain).is_err());
+        assert_eq!(header("http://gitlab.local/api/v4/projects/g%2Fp"), None);
+    }
+
+    #[test]
+    fn test_gitea_token_scope() {
+        let tokens = ForgeTokens {
+            gitea: Some("env".to_string()),
+            hosts: HashMap::from([("https://forgejo.corp".to_string(), "own".to_string())]),
+            declared: HashSet::from(["https://codeberg.org".to_string()]),
+            ..Default::default()
+        };
+        let fetcher = Fetcher::new("out".to_string(), PathBuf::from("."), None).with_tokens(tokens);
+        for spec in [
+            r#""forgejo:o/r/a.md""#,
+            r#""gitea:o/r/a.md""#,
+            r#"{ git = "forgejo:o/r/a.md", host = "https://forgejo.corp" }"#,
+            r#"{ git = "gitea:o/r/a.md", host = "https://attacker.example" }"#,
+        ] {
+            fetcher.authorize(&source(spec)).unwrap();
+        }
+
+        let header = |url: &str| fetcher.client.credential(url);
+        assert_eq!(
+            header("https://codeberg.org/api/v1/repos/o/r"),
+            Some(("Authorization", "token env".to_string()))
+        );
+        assert_eq!(
+            header("https://forgejo.corp/api/v1/repos/o/r"),
+            Some(("Authorization", "token own".to_string()))
+        );
+        // 未在本项目中声明的 gitea.com 也拿不到 token
+        assert_eq!(header("https://gitea.com/api/v1/repos/o/r"), None);
+        assert_eq!(header("https://attacker.example/api/v1/repos/o/r"), None);
+    }
+
+    #[test]
+    fn test_bitbucket_credentials_scope() {
+        let tokens = ForgeTokens {
+            bitbucket: Some("Basic dTpw".to_string()),
+            hosts: HashMap::from([("https://bb.corp".to_string(), "own".to_string())]),
+            declared: HashSet::from(["https://bb.mine".to_string()]),
+            ..Default::default()
+        };
+        let fetcher = Fetcher::new("out".to_string(), PathBuf::from("."), None).with_tokens(tokens);
+        for host in [
+            "https://bitbucket.org",
+            "https://bb.mine",
+            "https://bb.corp",
+            "https://attacker.example",
+        ] {
+            let spec = format!(r#"{{ git = "bitbucket:OPS/r/a.md", host = "{}" }}"#, host);
+            fetcher.authorize(&source(&spec)).unwrap();
+        }
+
+        let header = |url: &str| fetcher.client.credential(url);
+        let env = Some(("Authorization", "Basic dTpw".to_string()));
+        // Cloud 的凭据登记在 API 主机上
+        assert_eq!(header("https://api.bitbucket.org/2.0/repositories/OPS/r"), env);
+        assert_eq!(header("https://bb.mine/rest/api/1.0/projects/OPS/repos/r"), env);
+        assert_eq!(
+            header("https://bb.corp/rest/api/1.0/projects/OPS/repos/r"),
+            Some(("Authorization", "Bearer own".to_string()))
+        );
+        assert_eq!(header("https://attacker.example/rest/api/1.0/projects/OPS/repos/r"), None);
+    }
+
+    /// A fresh project directory with the given source files.
+    fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cardo-fetcher-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, content) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn dependencies(manifest: &str) -> HashMap<String, Dependency> {
+        let table: toml::Table = toml::from_str(manifest).unwrap();
+        table
+            .iter()
+            .map(|(name, value)| (name.clone(), Dependency::parse(value).unwrap()))
+            .collect()
+    }
+
+    fn read(path: PathBuf) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_all_or_nothing() {
+        let dir = project(
+            "all-or-nothing",
+            &[
+                ("src/a.md", "new a"),
+                ("src/z.md", "new z"),
+                ("out/a.md", "old a"),
+                ("out/.cardo-manifest", "a.md\talpha\n"),
+                // 普通文件挡住了 zulu 的目标目录，提交到它时才会失败
+                ("out/blocked", "mine"),
+            ],
+        );
+        let out = dir.join("out");
+        let fetcher = Fetcher::new(out.to_string_lossy().into_owned(), dir.clone(), None);
+        let dependencies = dependencies(
+            r#"
+            alpha = { path = "src/a.md", to = "a.md" }
+            zulu = { path = "src/z.md", to = "blocked/z.md" }
+            "#,
+        );
+        let staging_left = || {
+            std::fs::read_dir(&out)
+                .unwrap()
+                .any(|e| e.unwrap().file_name().to_string_lossy().starts_with(".cardo-staging"))
+        };
+
+        // alpha 已经提交，zulu 失败后撤销 alpha 的改动
+        let options = FetchOptions {
+            all_or_nothing: true,
+            ..Default::default()
+        };
+        let report = fetcher.fetch_all(&dependencies, &LockFile::default(), &options).await.unwrap();
+        assert!(!report.applied);
+        assert!(report.results.iter().any(|r| r.name == "zulu" && !r.success));
+        assert_eq!(read(out.join("a.md")), "old a");
+        assert_eq!(read(out.join("blocked")), "mine");
+        assert_eq!(read(out.join(".cardo-manifest")), "a.md\talpha\n");
+        assert!(!staging_left());
+
+        // --keep-going 照常写入成功的依赖
+        let options = FetchOptions::default();
+        let report = fetcher.fetch_all(&dependencies, &LockFile::default(), &options).await.unwrap();
+        assert!(report.applied);
+        let success: Vec<_> = report.results.iter().map(|r| (r.name.as_str(), r.success)).collect();
+        assert_eq!(success, vec![("alpha", true), ("zulu", false)]);
+        assert_eq!(read(out.join("a.md")), "new a");
+        assert_eq!(read(out.join("blocked")), "mine");
+        assert!(!staging_left());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recover_interrupted() {
+        // 上一次抓取在提交途中被中断：a.md 已替换，b.md 记入日志但还没移走，new.md 是新文件
+        let staging = format!("out/{}1", STAGING_PREFIX);
+        let dir = project(
+            "recover",
+            &[
+                ("out/.cardo-manifest", "a.md\talpha\nb.md\tbeta\n"),
+                ("out/a.md", "new a"),
+                ("out/b.md", "old b"),
+                ("out/docs/new.md", "new"),
+                (&format!("{}/backup/0", staging), "old a"),
+                (&format!("{}/{}", staging, JOURNAL_FILE), "a.md\t0\nb.md\t1\ndocs/new.md\t\n"),
+            ],
+        );
+        let out = dir.join("out");
+        let fetcher = Fetcher::new(out.to_string_lossy().into_owned(), dir.clone(), None);
+        let options = FetchOptions {
+            prune: false,
+            ..Default::default()
+        };
+        fetcher
+            .fetch_all(&HashMap::new(), &LockFile::default(), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(read(out.join("a.md")), "old a");
+        assert_eq!(read(out.join("b.md")), "old b");
+        assert!(!out.join("docs").exists());
+        assert!(!dir.join(staging).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unmanaged_destination() {
+        let dir = project("unmanaged", &[("src/a.md", "a"), ("out/docs/a.md", "mine")]);
+        let out = dir.join("out");
+        let fetcher = Fetcher::new(out.to_string_lossy().into_owned(), dir.clone(), None);
+        let lock = LockFile::default();
+        let dependencies = dependencies(r#"alpha = { path = "src/a.md", to = "docs/a.md" }"#);
+
+        // 手写的文件既不被覆盖，也不会记为 cardo 写入的文件
+        let error = fetcher
+            .fetch_all(&dependencies, &lock, &FetchOptions::default())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("`alpha` would overwrite docs/a.md"));
+        assert_eq!(read(out.join("docs/a.md")), "mine");
+        assert!(ManagedFiles::load(&out).unwrap().is_none());
+
+        let options = FetchOptions {
+            force: true,
+            ..Default::default()
+        };
+        let report = fetcher.fetch_all(&dependencies, &lock, &options).await.unwrap();
+        assert!(report.results.iter().all(|r| r.success));
+        assert_eq!(read(out.join("docs/a.md")), "a");
+        assert!(ManagedFiles::load(&out).unwrap().unwrap().contains("docs/a.md"));
+
+        // 之后的抓取照常更新 cardo 写入的文件
+        std::fs::write(dir.join("src/a.md"), "new a").unwrap();
+        fetcher
+            .fetch_all(&dependencies, &lock, &FetchOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(read(out.join("docs/a.md")), "new a");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let dir = project(
+            "prune",
+            &[
+                ("src/a.md", "a"),
+                ("src/b.md", "b"),
+                ("src/c.md", "c"),
+                ("out/notes.md", "mine"),
+            ],
+        );
+        let out = dir.join("out");
+        let out_dir = out.to_string_lossy().into_owned();
+        let fetcher = Fetcher::new(out_dir.clone(), dir.clone(), None);
+        let lock = LockFile::default();
+        let options = FetchOptions::default();
+        let pruned = |report: FetchReport| {
+            let mut pruned: Vec<_> = report
+                .pruned
+                .iter()
+                .map(|p| p.strip_prefix(&format!("{}/", out_dir)).unwrap().to_string())
+                .collect();
+            pruned.sort();
+            pruned
+        };
+
+        let all = dependencies(
+            r#"
+            alpha = { path = "src/a.md", to = "a.md" }
+            beta = { path = "src/b.md", to = "b.md" }
+            gamma = { path = "src/c.md", to = "c.md" }
+            "#,
+        );
+        let report = fetcher.fetch_all(&all, &lock, &options).await.unwrap();
+        assert!(pruned(report).is_empty());
+
+        // 删除 beta、把 alpha 移到别处，旧文件都被删除
+        let moved = dependencies(
+            r#"
+            alpha = { path = "src/a.md", to = "docs/a.md" }
+            gamma = { path = "src/c.md", to = "c.md" }
+            "#,
+        );
+        let report = fetcher.fetch_all(&moved, &lock, &options).await.unwrap();
+        assert_eq!(pruned(report), vec!["a.md", "b.md"]);
+        assert_eq!(read(out.join("docs/a.md")), "a");
+
+        // 旧版本的记录没有依赖名；gamma 失败时它和 gamma 的文件都保留
+        std::fs::write(out.join("legacy.md"), "legacy").unwrap();
+        let record = out.join(crate::managed::MANAGED_FILE_NAME);
+        std::fs::write(&record, format!("{}legacy.md\n", read(record.clone()))).unwrap();
+        std::fs::remove_file(dir.join("src/c.md")).unwrap();
+        let report = fetcher.fetch_all(&moved, &lock, &options).await.unwrap();
+        assert!(report.results.iter().any(|r| r.name == "gamma" && !r.success));
+        assert!(pruned(report).is_empty());
+        assert_eq!(read(out.join("c.md")), "c");
+        assert_eq!(read(out.join("legacy.md")), "legacy");
+
+        // 全部成功后才删除无主的旧记录
+        let only_alpha = dependencies(r#"alpha = { path = "src/a.md", to = "docs/a.md" }"#);
+        let report = fetcher.fetch_all(&only_alpha, &lock, &options).await.unwrap();
+        assert_eq!(pruned(report), vec!["c.md", "legacy.md"]);
+
+        // 手写的文件从未被记录，始终保留
+        assert_eq!(read(out.join("notes.md")), "mine");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reproduce_rewritten() {
+        let dir = project("reproduce", &[("out/guide.md", "[a](X/a.md)")]);
+        let out = dir.join("out");
+        let mut fetcher = Fetcher::new(out.to_string_lossy().into_owned(), dir.clone(), None);
+        fetcher.cache = None;
+        let file = LockedFile {
+            path: "guide.md".to_string(),
+            url: "https://raw.githubusercontent.com/o/r/0123abc/guide.md".to_string(),
+            sha256: utils::sha256_hex(b"[a](a.md)"),
+            rewritten: Some(utils::sha256_hex(b"[a](X/a.md)")),
+        };
+        let options = FetchOptions {
+            offline: true,
+            ..Default::default()
+        };
+
+        // 没有缓存时按改写后的哈希比较，离线也能确认文件无需更新
+        assert!(fetcher.reproduce(&file, true, &options).await.unwrap().is_none());
+        // 不再改写链接时，改写过的文件需要重新加载
+        let error = fetcher.reproduce(&file, false, &options).await.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(FetchError::NotCached(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clean() {
+        let dir = project(
+            "clean",
+            &[("src/a.md", "a"), ("shared/x.md", "x"), ("out/notes.md", "mine")],
+        );
+        let out = dir.join("out");
+        let out_dir = out.to_string_lossy().into_owned();
+        let fetcher = Fetcher::new(out_dir.clone(), dir.clone(), None);
+        let lock = LockFile::default();
+        let dependencies = dependencies(r#"alpha = { path = "src/a.md", to = "docs/a.md" }"#);
+        fetcher
+            .fetch_all(&dependencies, &lock, &FetchOptions::default())
+            .await
+            .unwrap();
+
+        // 记录中位于符号链接目录下的文件不能删除，记录要保留
+        utils::symlink(&dir.join("shared"), &out.join("link"), true).unwrap();
+        let record = out.join(crate::managed::MANAGED_FILE_NAME);
+        std::fs::write(&record, format!("{}link/x.md\n", read(record.clone()))).unwrap();
+
+        let report = fetcher.clean(&lock, false, true).await.unwrap();
+        assert_eq!(report.removed, vec![format!("{}/docs/a.md", out_dir)]);
+        assert!(report.kept.contains(&format!("{}/notes.md", out_dir)));
+        assert_eq!(read(out.join("docs/a.md")), "a");
+
+        let report = fetcher.clean(&lock, false, false).await.unwrap();
+        assert_eq!(report.removed, vec![format!("{}/docs/a.md", out_dir)]);
+        assert!(!out.join("docs").exists());
+        assert_eq!(read(out.join("notes.md")), "mine");
+        assert_eq!(read(dir.join("shared/x.md")), "x");
+        let managed = ManagedFiles::load(&out).unwrap().unwrap();
+        assert_eq!(managed.iter().collect::<Vec<_>>(), vec![("link/x.md", None)]);
+
+        // --all 清空整个输出目录，但不会经由符号链接删除源文件
+        let report = fetcher.clean(&lock, true, false).await.unwrap();
+        assert!(report.removed.contains(&format!("{}/notes.md", out_dir)));
+        assert_eq!(std::fs::read_dir(&out).unwrap().count(), 0);
+        assert_eq!(read(dir.join("shared/x.md")), "x");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_upstream_replay() {
+        let guide_sha256 = utils::sha256_hex(b"# Guide");
+        let dir = project(
+            "upstream-replay",
+            &[
+                ("upstream/docs/a.md", "# A"),
+                ("upstream/docs/guide.md", "# Guide"),
+                (
+                    "upstream/markdown.toml",
+                    &format!(
+                        "[dependencies]\nguide = {{ path = \"docs/guide.md\", sha256 = \"{}\", \
+                         to = \"../guide.md\" }}\n",
+                        guide_sha256
+                    ),
+                ),
+            ],
+        );
+        let upstream = dir.join("upstream");
+        for args in [&["init", "--quiet"][..], &["add", "."], &["commit", "--quiet", "-m", "init"]] {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(&upstream)
+                .args(["-c", "user.name=cardo", "-c", "user.email=cardo@example.com"])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?} failed", args);
+        }
+
+        let out = dir.join("out");
+        let fetcher = Fetcher::new(out.to_string_lossy().into_owned(), dir.clone(), None);
+        let options = FetchOptions::default();
+        let dependencies = dependencies(&format!(
+            r#"alpha = {{ git = "file://{}", path = "docs/a.md", to = "a.md" }}"#,
+            upstream.display()
+        ));
+        let locked = |report: FetchReport| {
+            assert!(report.results.iter().all(|r| r.success));
+            LockFile::new(report.results.into_iter().filter_map(|r| r.locked).collect())
+        };
+
+        let report = fetcher
+            .fetch_all(&dependencies, &LockFile::default(), &options)
+            .await
+            .unwrap();
+        let mut lock = locked(report);
+        let guide = lock.get("guide").unwrap();
+        assert_eq!(guide.sha256.as_deref(), Some(guide_sha256.as_str()));
+        assert!(lock.get("alpha").unwrap().sha256.is_none());
+
+        // 上游声明的 `to` 被忽略，文件按本项目的布局放置
+        let path = guide.files[0].path.clone();
+        assert!(path.ends_with("docs/guide.md"));
+
+        // 按锁文件复现时记录保持不变
+        std::fs::remove_dir_all(&out).unwrap();
+        let report = fetcher.fetch_all(&dependencies, &lock, &options).await.unwrap();
+        assert_eq!(read(out.join(&path)), "# Guide");
+        assert_eq!(locked(report).packages, lock.packages);
+
+        // 上游声明的校验和同样要通过
+        lock.packages.iter_mut().find(|p| p.name == "guide").unwrap().sha256 =
+            Some(utils::sha256_hex(b"other"));
+        let report = fetcher.fetch_all(&dependencies, &lock, &options).await.unwrap();
+        let guide = report.results.iter().find(|r| r.name == "guide").unwrap();
+        assert!(!guide.success);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,95 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub const LOCK_FILE_NAME: &str = ".cardo-lock";
+
+/// An advisory lock on a project, held by commands that write to it so that two cardo
+/// processes never interleave their writes. Released when dropped.
+#[derive(Debug)]
+pub struct ProjectLock {
+    _file: File,
+}
+
+#[derive(Debug, Error)]
+pub enum LockError {
+    #[error("{} is locked by another cardo process{}", .path.display(), .pid.map(|pid| format!(" (pid {})", pid)).unwrap_or_default())]
+    Busy { path: PathBuf, pid: Option<u32> },
+    #[error("Failed to lock {}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl ProjectLock {
+    /// Locks `project_dir`. If another process holds the lock, waits for it after calling
+    /// `on_wait` with that process's pid, or fails right away when `wait` is false.
+    pub fn acquire(
+        project_dir: &Path,
+        wait: bool,
+        on_wait: impl FnOnce(Option<u32>),
+    ) -> Result<Self, LockError> {
+        let path = project_dir.join(LOCK_FILE_NAME);
+        let io_error = |source| LockError::Io {
+            path: path.clone(),
+            source,
+        };
+
+        // 不截断：持有者写入的 pid 要留给等待者读取
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(io_error)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let pid = read_pid(&mut file);
+                if !wait {
+                    return Err(LockError::Busy { path, pid });
+                }
+                on_wait(pid);
+                file.lock().map_err(io_error)?;
+            }
+            Err(TryLockError::Error(e)) => return Err(io_error(e)),
+        }
+
+        file.set_len(0).map_err(io_error)?;
+        file.rewind().map_err(io_error)?;
+        writeln!(file, "{}", std::process::id()).map_err(io_error)?;
+        Ok(Self { _file: file })
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_lock_is_busy() {
+        let dir = std::env::temp_dir().join(format!("cardo-flock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let lock = ProjectLock::acquire(&dir, false, |_| {}).unwrap();
+        match ProjectLock::acquire(&dir, false, |_| {}) {
+            Err(LockError::Busy { pid, .. }) => assert_eq!(pid, Some(std::process::id())),
+            other => panic!("expected the lock to be busy, got {:?}", other),
+        }
+
+        drop(lock);
+        ProjectLock::acquire(&dir, false, |_| {}).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
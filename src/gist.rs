@@ -0,0 +1,126 @@
+use crate::github::{GitHubClient, GitHubError};
+use crate::utils::{percent_decode, percent_encode};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+const API_URL: &str = "https://api.github.com/gists";
+const WEB_URL: &str = "https://gist.github.com";
+
+#[derive(Deserialize)]
+struct Gist {
+    #[serde(default)]
+    files: BTreeMap<String, IgnoredAny>,
+    #[serde(default)]
+    history: Vec<Revision>,
+}
+
+#[derive(Deserialize)]
+struct Revision {
+    version: String,
+}
+
+impl Gist {
+    /// Newest revision whose SHA starts with `rev`, or the newest of all.
+    fn revision(self, rev: Option<&str>) -> Option<String> {
+        // history 按时间倒序排列，第一项是最新修订
+        self.history
+            .into_iter()
+            .map(|revision| revision.version)
+            .find(|version| rev.is_none_or(|rev| version.starts_with(rev)))
+    }
+}
+
+/// Raw download URL of `file` at `revision`; gist.github.com redirects it to the owner's raw URL.
+pub fn raw_file_url(id: &str, revision: &str, file: &str) -> String {
+    format!("{}/{}/raw/{}/{}", WEB_URL, id, revision, percent_encode(file))
+}
+
+/// File name in a `raw_file_url`, or `None` if `url` is not one for this gist and revision.
+pub fn file_path(id: &str, revision: &str, url: &str) -> Option<String> {
+    let prefix = format!("{}/{}/raw/{}/", WEB_URL, id, revision);
+    percent_decode(url.strip_prefix(&prefix)?)
+}
+
+/// Page showing `file` at `revision`, anchored the way the gist page names its files.
+pub fn web_url(id: &str, revision: &str, file: &str) -> String {
+    let anchor: String = file
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("{}/{}/{}#file-{}", WEB_URL, id, revision, anchor)
+}
+
+/// Resolves `rev` (possibly abbreviated) to a full revision SHA, or the latest revision if `None`.
+pub async fn resolve_revision(
+    client: &GitHubClient,
+    id: &str,
+    rev: Option<&str>,
+) -> Result<String, GitHubError> {
+    let gist: Gist = client
+        .get_json(&format!("{}/{}", API_URL, id), &format!("gist {}", id))
+        .await?;
+    gist.revision(rev)
+        .ok_or_else(|| GitHubError::NotFound(format!("gist {}@{}", id, rev.unwrap_or("latest"))))
+}
+
+/// Names of the gist's files at `revision`.
+pub async fn list_files(
+    client: &GitHubClient,
+    id: &str,
+    revision: &str,
+) -> Result<Vec<String>, GitHubError> {
+    let gist: Gist = client
+        .get_json(
+            &format!("{}/{}/{}", API_URL, id, revision),
+            &format!("gist {}@{}", id, revision),
+        )
+        .await?;
+    Ok(gist.files.into_keys().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gist() {
+        // GET /gists/{id} 的响应，省略了无关字段
+        let recorded = r#"{
+            "id": "aa5a315d61ae9438b18d",
+            "files": {
+                "Prompt.md": {"filename": "Prompt.md", "type": "text/markdown", "size": 120},
+                "check list.md": {"filename": "check list.md", "type": "text/markdown", "size": 48}
+            },
+            "history": [
+                {"version": "57a7f021a713b1c5a6a199b54cc514735d2d462f", "committed_at": "2024-05-02T10:00:00Z"},
+                {"version": "0123abc9d8e7f6a5b4c3d2e1f0a9b8c7d6e5f4a3", "committed_at": "2024-04-01T09:00:00Z"}
+            ]
+        }"#;
+        let gist = || serde_json::from_str::<Gist>(recorded).unwrap();
+
+        assert_eq!(
+            gist().revision(None).as_deref(),
+            Some("57a7f021a713b1c5a6a199b54cc514735d2d462f")
+        );
+        assert_eq!(
+            gist().revision(Some("0123abc")).as_deref(),
+            Some("0123abc9d8e7f6a5b4c3d2e1f0a9b8c7d6e5f4a3")
+        );
+        assert_eq!(gist().revision(Some("fff")), None);
+        assert_eq!(
+            gist().files.into_keys().collect::<Vec<_>>(),
+            vec!["Prompt.md", "check list.md"]
+        );
+        assert_eq!(
+            web_url("aa5a315d61ae9438b18d", "0123abc", "Prompt.md"),
+            "https://gist.github.com/aa5a315d61ae9438b18d/0123abc#file-prompt-md"
+        );
+    }
+}
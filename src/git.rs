@@ -0,0 +1,255 @@
+use crate::utils;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tokio::process::Command;
+
+const LOCATOR_PREFIX: &str = "git+";
+
+#[derive(Debug, Error)]
+pub enum GitError {
+    #[error("Failed to run git (is it installed?): {0}")]
+    Spawn(#[from] io::Error),
+    #[error("File not found: {0}")]
+    NotFound(String),
+    #[error("Failed to fetch from {0}: {1}")]
+    Remote(String, String),
+    #[error("git {0} failed: {1}")]
+    Command(String, String),
+}
+
+/// Reads files from arbitrary git repositories through the `git` command line, keeping
+/// a shallow bare clone of each repository so later runs only fetch what is missing.
+pub struct GitClient {
+    root: PathBuf,
+    // 同一仓库的 fetch 串行执行，避免争用 shallow 等文件的锁
+    repo_locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// Identifies `path` in repository `url` at `commit`, in the form stored in markdown.lock.
+pub fn file_locator(url: &str, commit: &str, path: &str) -> String {
+    format!("{}{}#{}:{}", LOCATOR_PREFIX, url, commit, path)
+}
+
+/// Splits a `file_locator` into repository URL, commit and path.
+pub fn parse_locator(locator: &str) -> Option<(&str, &str, &str)> {
+    let (url, rest) = locator.strip_prefix(LOCATOR_PREFIX)?.rsplit_once('#')?;
+    let (commit, path) = rest.split_once(':')?;
+    Some((url, commit, path))
+}
+
+impl GitClient {
+    /// Keeps clones under `root`, one directory per repository URL.
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            repo_locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn repo_dir(&self, url: &str) -> PathBuf {
+        self.root.join(utils::sha256_hex(url.as_bytes()))
+    }
+
+    async fn run(&self, dir: Option<&Path>, args: &[&str]) -> Result<Vec<u8>, GitError> {
+        let mut command = Command::new("git");
+        if let Some(dir) = dir {
+            command.arg("-C").arg(dir);
+        }
+        // 不在终端交互式询问凭据
+        let output = command
+            .args(args)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .output()
+            .await?;
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(GitError::Command(
+                args.first().copied().unwrap_or_default().to_string(),
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ))
+        }
+    }
+
+    /// Runs a command that talks to `url`, telling missing refs apart from connection problems.
+    /// Callers put `--end-of-options` before the URL and refs so neither is read as an option.
+    async fn run_remote(&self, url: &str, dir: Option<&Path>, args: &[&str]) -> Result<Vec<u8>, GitError> {
+        self.run(dir, args).await.map_err(|e| match e {
+            GitError::Command(_, message)
+                if message.contains("couldn't find remote ref")
+                    || message.contains("not our ref")
+                    || message.contains("does not appear to be a git repository")
+                    || message.contains("not found") =>
+            {
+                GitError::NotFound(format!("{}: {}", url, message))
+            }
+            GitError::Command(_, message) => GitError::Remote(url.to_string(), message),
+            e => e,
+        })
+    }
+
+    /// Opens the clone of `url`, creating an empty one on first use, and holds its lock.
+    async fn open(&self, url: &str) -> Result<(PathBuf, tokio::sync::OwnedMutexGuard<()>), GitError> {
+        let lock = self
+            .repo_locks
+            .lock()
+            .unwrap()
+            .entry(url.to_string())
+            .or_default()
+            .clone();
+        let guard = lock.lock_owned().await;
+
+        let dir = self.repo_dir(url);
+        if !dir.join("HEAD").exists() {
+            tokio::fs::create_dir_all(&dir).await?;
+            self.run(Some(&dir), &["init", "--bare", "--quiet"]).await?;
+        }
+        Ok((dir, guard))
+    }
+
+    /// Name of the branch `HEAD` points to in the remote repository.
+    pub async fn default_branch(&self, url: &str) -> Result<String, GitError> {
+        let output = self
+            .run_remote(url, None, &["ls-remote", "--symref", "--end-of-options", url, "HEAD"])
+            .await?;
+        // 输出形如 `ref: refs/heads/main\tHEAD`
+        String::from_utf8_lossy(&output)
+            .lines()
+            .find_map(|line| line.strip_prefix("ref: refs/heads/"))
+            .and_then(|line| line.split_once('\t'))
+            .map(|(branch, _)| branch.to_string())
+            .ok_or_else(|| GitError::NotFound(format!("{} has no default branch", url)))
+    }
+
+    /// Fetches `git_ref` (a branch, tag or commit) and returns the full commit SHA.
+    pub async fn resolve_commit(&self, url: &str, git_ref: &str) -> Result<String, GitError> {
+        if utils::is_full_sha(git_ref) {
+            self.ensure_commit(url, git_ref).await?;
+            return Ok(git_ref.to_string());
+        }
+
+        let (dir, _guard) = self.open(url).await?;
+        let fetched = self
+            .run_remote(
+                url,
+                Some(&dir),
+                &["fetch", "--quiet", "--depth", "1", "--end-of-options", url, git_ref],
+            )
+            .await;
+        let target = match fetched {
+            Ok(_) => "FETCH_HEAD^{commit}".to_string(),
+            // 缩写的提交 SHA 无法直接 fetch，只能取回完整历史后查找
+            Err(GitError::NotFound(_)) if git_ref.chars().all(|c| c.is_ascii_hexdigit()) => {
+                let mut args = vec!["fetch", "--quiet", "--tags"];
+                if dir.join("shallow").exists() {
+                    args.push("--unshallow");
+                }
+                args.extend(["--end-of-options", url, "+refs/heads/*:refs/heads/*"]);
+                self.run_remote(url, Some(&dir), &args).await?;
+                format!("{}^{{commit}}", git_ref)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let output = self
+            .run(Some(&dir), &["rev-parse", "--verify", "--quiet", "--end-of-options", &target])
+            .await
+            .map_err(|_| GitError::NotFound(format!("{}@{}", url, git_ref)))?;
+        Ok(String::from_utf8_lossy(&output).trim().to_string())
+    }
+
+    /// Makes sure `commit` is in the local clone, fetching just that commit if needed.
+    async fn ensure_commit(&self, url: &str, commit: &str) -> Result<PathBuf, GitError> {
+        let (dir, _guard) = self.open(url).await?;
+        let object = format!("{}^{{commit}}", commit);
+        if self.run(Some(&dir), &["cat-file", "-e", "--end-of-options", &object]).await.is_err() {
+            self.run_remote(
+                url,
+                Some(&dir),
+                &["fetch", "--quiet", "--depth", "1", "--end-of-options", url, commit],
+            )
+            .await?;
+        }
+        Ok(dir)
+    }
+
+    /// Lists every file in the repository at `commit`.
+    pub async fn list_files(&self, url: &str, commit: &str) -> Result<Vec<String>, GitError> {
+        let dir = self.ensure_commit(url, commit).await?;
+        let output = self
+            .run(Some(&dir), &["ls-tree", "-r", "-z", "--name-only", "--end-of-options", commit])
+            .await?;
+        Ok(output
+            .split(|b| *b == 0)
+            .filter(|path| !path.is_empty())
+            .map(|path| String::from_utf8_lossy(path).to_string())
+            .collect())
+    }
+
+    /// Reads the file a `file_locator` points at.
+    pub async fn read_file(&self, locator: &str) -> Result<Vec<u8>, GitError> {
+        let (url, commit, path) =
+            parse_locator(locator).ok_or_else(|| GitError::NotFound(locator.to_string()))?;
+        let dir = self.ensure_commit(url, commit).await?;
+        self.run(Some(&dir), &["cat-file", "--end-of-options", "blob", &format!("{}:{}", commit, path)])
+            .await
+            .map_err(|_| GitError::NotFound(locator.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locator_roundtrip() {
+        let locator = file_locator("https://git.example.com/org/docs.git", "0123abc", "a/b.md");
+        assert_eq!(locator, "git+https://git.example.com/org/docs.git#0123abc:a/b.md");
+        assert_eq!(
+            parse_locator(&locator),
+            Some(("https://git.example.com/org/docs.git", "0123abc", "a/b.md"))
+        );
+        assert_eq!(parse_locator("https://example.com/a.md"), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_from_local_repository() {
+        let root = std::env::temp_dir().join(format!("cardo-git-{}", std::process::id()));
+        let upstream = root.join("upstream");
+        std::fs::create_dir_all(upstream.join("docs")).unwrap();
+        std::fs::write(upstream.join("docs/a.md"), "# A\n").unwrap();
+
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(&upstream)
+                .args(["-c", "user.name=cardo", "-c", "user.email=cardo@example.com"])
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?} failed", args);
+        };
+        git(&["init", "--quiet", "--initial-branch", "main"]);
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "-m", "init"]);
+        git(&["tag", "v1"]);
+
+        let url = format!("file://{}", upstream.display());
+        let client = GitClient::new(root.join("clones"));
+        assert_eq!(client.default_branch(&url).await.unwrap(), "main");
+        let commit = client.resolve_commit(&url, "v1").await.unwrap();
+        assert_eq!(client.resolve_commit(&url, "main").await.unwrap(), commit);
+        assert_eq!(client.list_files(&url, &commit).await.unwrap(), vec!["docs/a.md"]);
+
+        let locator = file_locator(&url, &commit, "docs/a.md");
+        assert_eq!(client.read_file(&locator).await.unwrap(), b"# A\n");
+        let missing = file_locator(&url, &commit, "docs/b.md");
+        assert!(matches!(client.read_file(&missing).await, Err(GitError::NotFound(_))));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
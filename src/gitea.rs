@@ -0,0 +1,205 @@
+use crate::github::{GitHubClient, GitHubError};
+use crate::utils::{percent_decode, percent_encode, percent_encode_path};
+use serde::Deserialize;
+
+/// Base URL used by `gitea:` dependencies without their own `host`.
+pub const DEFAULT_HOST: &str = "https://gitea.com";
+/// Base URL used by `forgejo:` dependencies without their own `host`.
+pub const CODEBERG_HOST: &str = "https://codeberg.org";
+/// Environment variable holding the token sent to Gitea and Forgejo hosts.
+pub const TOKEN_ENV: &str = "GITEA_TOKEN";
+const TREE_PAGE_SIZE: usize = 1000;
+
+#[derive(Deserialize)]
+struct Repository {
+    default_branch: String,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct Tree {
+    #[serde(default)]
+    tree: Vec<TreeEntry>,
+    #[serde(default)]
+    truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct TreeEntry {
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+fn repo_url(host: &str, owner: &str, repo: &str) -> String {
+    format!(
+        "{}/api/v1/repos/{}/{}",
+        host,
+        percent_encode(owner),
+        percent_encode(repo)
+    )
+}
+
+/// Raw download URL of `path` at `git_ref` through the repository raw API.
+pub fn raw_file_url(host: &str, owner: &str, repo: &str, git_ref: &str, path: &str) -> String {
+    format!(
+        "{}/raw/{}?ref={}",
+        repo_url(host, owner, repo),
+        percent_encode_path(path),
+        percent_encode(git_ref)
+    )
+}
+
+/// Inverse of `raw_file_url`; `None` for URLs of another repository or ref.
+pub fn file_path(host: &str, owner: &str, repo: &str, git_ref: &str, url: &str) -> Option<String> {
+    let prefix = format!("{}/raw/", repo_url(host, owner, repo));
+    let suffix = format!("?ref={}", percent_encode(git_ref));
+    percent_decode(url.strip_prefix(&prefix)?.strip_suffix(&suffix)?)
+}
+
+/// Page showing `path` at `commit` in the Gitea or Forgejo web interface.
+pub fn web_url(host: &str, owner: &str, repo: &str, commit: &str, path: &str) -> String {
+    format!("{}/{}/{}/src/commit/{}/{}", host, owner, repo, commit, path)
+}
+
+pub async fn default_branch(
+    client: &GitHubClient,
+    host: &str,
+    owner: &str,
+    repo: &str,
+) -> Result<String, GitHubError> {
+    let found: Repository = client
+        .get_json(&repo_url(host, owner, repo), &format!("{}/{}", owner, repo))
+        .await?;
+    Ok(found.default_branch)
+}
+
+/// Takes the newest entry of the commit list starting at `git_ref`.
+pub async fn resolve_commit(
+    client: &GitHubClient,
+    host: &str,
+    owner: &str,
+    repo: &str,
+    git_ref: &str,
+) -> Result<String, GitHubError> {
+    // `sha` 参数接受分支、标签或提交
+    let url = format!(
+        "{}/commits?sha={}&limit=1&stat=false&verification=false&files=false",
+        repo_url(host, owner, repo),
+        percent_encode(git_ref)
+    );
+    let what = format!("{}/{}@{}", owner, repo, git_ref);
+    let commits: Vec<Commit> = client.get_json(&url, &what).await?;
+    commits
+        .into_iter()
+        .next()
+        .map(|commit| commit.sha)
+        .ok_or(GitHubError::NotFound(what))
+}
+
+/// Every blob in the recursive Git tree at `commit`, requesting further pages while the
+/// response is marked `truncated`.
+pub async fn list_tree(
+    client: &GitHubClient,
+    host: &str,
+    owner: &str,
+    repo: &str,
+    commit: &str,
+) -> Result<Vec<String>, GitHubError> {
+    let mut paths = Vec::new();
+    for page in 1.. {
+        let url = format!(
+            "{}/git/trees/{}?recursive=true&per_page={}&page={}",
+            repo_url(host, owner, repo),
+            percent_encode(commit),
+            TREE_PAGE_SIZE,
+            page
+        );
+        let tree: Tree = client
+            .get_json(&url, &format!("{}/{}@{}", owner, repo, commit))
+            .await?;
+        paths.extend(
+            tree.tree
+                .into_iter()
+                .filter(|entry| entry.kind == "blob")
+                .map(|entry| entry.path),
+        );
+        if !tree.truncated {
+            break;
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn test_api() {
+        let api = "/api/v1/repos/org/docs";
+        let commit = "7d1e0a9c8b6f5e4d3c2b1a0f9e8d7c6b5a4f3e2d";
+        let commits = |git_ref: &str| {
+            format!(
+                "{}/commits?sha={}&limit=1&stat=false&verification=false&files=false",
+                api, git_ref
+            )
+        };
+        let tree = |page: usize| {
+            format!(
+                "{}/git/trees/{}?recursive=true&per_page={}&page={}",
+                api, commit, TREE_PAGE_SIZE, page
+            )
+        };
+        let routes = [
+            (
+                api.to_string(),
+                r#"{"id":7,"full_name":"org/docs","private":false,"default_branch":"main"}"#
+                    .to_string(),
+            ),
+            (
+                commits("v1"),
+                format!(r#"[{{"sha":"{}","commit":{{"message":"Release v1\n"}}}}]"#, commit),
+            ),
+            // 找不到时 Gitea 返回空列表而不是 404
+            (commits("gone"), "[]".to_string()),
+            // 截断的树分页返回，直到 truncated 为 false
+            (
+                tree(1),
+                format!(
+                    r#"{{"sha":"{}","tree":[{{"path":"guide","mode":"040000","type":"tree","sha":"1a"}},
+                        {{"path":"guide/a.md","mode":"100644","type":"blob","size":12,"sha":"2b"}}],
+                        "truncated":true,"page":1,"total_count":3}}"#,
+                    commit
+                ),
+            ),
+            (
+                tree(2),
+                format!(
+                    r#"{{"sha":"{}","tree":[
+                        {{"path":"guide/b.md","mode":"100644","type":"blob","size":8,"sha":"3c"}}],
+                        "truncated":false,"page":2,"total_count":3}}"#,
+                    commit
+                ),
+            ),
+        ];
+        let host = testing::serve(&routes).await;
+        let client = GitHubClient::new(None);
+
+        assert_eq!(default_branch(&client, &host, "org", "docs").await.unwrap(), "main");
+        assert_eq!(resolve_commit(&client, &host, "org", "docs", "v1").await.unwrap(), commit);
+        assert!(matches!(
+            resolve_commit(&client, &host, "org", "docs", "gone").await,
+            Err(GitHubError::NotFound(_))
+        ));
+        assert_eq!(
+            list_tree(&client, &host, "org", "docs", commit).await.unwrap(),
+            vec!["guide/a.md", "guide/b.md"]
+        );
+    }
+}
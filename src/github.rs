@@ -1,8 +1,15 @@
-use crate::dependency::DependencySource;
 use anyhow::Result;
 use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use thiserror::Error;
+use tokio::sync::{OnceCell, OwnedSemaphorePermit, Semaphore};
+
+// 限制对同一主机的并发连接数，避免触发 GitHub 的滥用检测
+const MAX_CONNECTIONS_PER_HOST: usize = 4;
 
 #[derive(Debug, Error)]
 pub enum GitHubError {
@@ -12,11 +19,36 @@ pub enum GitHubError {
     NotFound(String),
     #[error("Network error: {0}")]
     NetworkError(String),
+    #[error("{0} is larger than the {1}-byte limit")]
+    TooLarge(String, u64),
 }
 
 pub struct GitHubClient {
     client: Client,
-    token: Option<String>,
+    // 按来源（scheme://host:port）记录认证头，令牌只发给对应的服务
+    credentials: Mutex<HashMap<String, (&'static str, String)>>,
+    host_limits: Mutex<HashMap<String, Arc<Semaphore>>>,
+    // 每个仓库的默认分支在一次运行中只查询一次
+    default_branches: Mutex<HashMap<String, Arc<OnceCell<String>>>>,
+}
+
+#[derive(Deserialize)]
+struct Repository {
+    default_branch: String,
+}
+
+#[derive(Deserialize)]
+struct Tree {
+    tree: Vec<TreeEntry>,
+    #[serde(default)]
+    truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct TreeEntry {
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
 }
 
 impl GitHubClient {
@@ -27,26 +59,105 @@ impl GitHubClient {
             .build()
             .expect("Failed to create HTTP client");
 
-        Self { client, token }
+        let mut credentials = HashMap::new();
+        if let Some(token) = token {
+            for origin in ["https://api.github.com", "https://raw.githubusercontent.com"] {
+                credentials.insert(origin.to_string(), ("Authorization", format!("token {}", token)));
+            }
+        }
+
+        Self {
+            client,
+            credentials: Mutex::new(credentials),
+            host_limits: Mutex::new(HashMap::new()),
+            default_branches: Mutex::new(HashMap::new()),
+        }
     }
 
-    pub async fn fetch_file(&self, source: &DependencySource) -> Result<String, GitHubError> {
-        let url = source.to_raw_url();
+    async fn acquire_host(&self, url: &str) -> OwnedSemaphorePermit {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_string))
+            .unwrap_or_default();
+        let semaphore = self
+            .host_limits
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(MAX_CONNECTIONS_PER_HOST)))
+            .clone();
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("host semaphore is never closed")
+    }
 
-        let mut request = self.client.get(&url);
+    /// Sends `header: value` with every request to the origin of `base_url`.
+    pub fn authorize(&self, base_url: &str, header: &'static str, value: &str) {
+        if let Some(origin) = origin(base_url) {
+            self.credentials
+                .lock()
+                .unwrap()
+                .insert(origin, (header, value.to_string()));
+        }
+    }
 
-        // 如果提供了 token，添加到请求头
-        if let Some(token) = &self.token {
-            request = request.header("Authorization", format!("token {}", token));
+    /// The header registered for the origin of `url`, if any.
+    pub(crate) fn credential(&self, url: &str) -> Option<(&'static str, String)> {
+        origin(url).and_then(|o| self.credentials.lock().unwrap().get(&o).cloned())
+    }
+
+    fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        let mut request = self.client.get(url);
+
+        // 如果该服务配置了 token，添加到请求头
+        if let Some((header, value)) = self.credential(url) {
+            request = request.header(header, value);
         }
 
-        let response = request.send().await?;
+        request
+    }
+
+    /// Fetches and decodes a JSON API response; a 404 becomes `NotFound(what)`.
+    pub async fn get_json<T: DeserializeOwned>(&self, url: &str, what: &str) -> Result<T, GitHubError> {
+        let _permit = self.acquire_host(url).await;
+        let response = self.get(url).send().await?;
 
         if response.status().is_success() {
-            let content = response.text().await?;
+            Ok(response.json().await?)
+        } else if response.status() == reqwest::StatusCode::NOT_FOUND {
+            Err(GitHubError::NotFound(what.to_string()))
+        } else {
+            Err(GitHubError::NetworkError(format!(
+                "HTTP {}: {}",
+                response.status(),
+                url
+            )))
+        }
+    }
+
+    /// Downloads `url` as raw bytes, giving up once the body exceeds `limit` bytes.
+    pub async fn fetch_url(&self, url: &str, limit: Option<u64>) -> Result<Vec<u8>, GitHubError> {
+        let _permit = self.acquire_host(url).await;
+        let mut response = self.get(url).send().await?;
+
+        if response.status().is_success() {
+            let limit = limit.unwrap_or(u64::MAX);
+            if response.content_length().is_some_and(|len| len > limit) {
+                return Err(GitHubError::TooLarge(url.to_string(), limit));
+            }
+
+            // 分块读取，服务器未声明长度时也能及时中止
+            let mut content = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                content.extend_from_slice(&chunk);
+                if content.len() as u64 > limit {
+                    return Err(GitHubError::TooLarge(url.to_string(), limit));
+                }
+            }
             Ok(content)
         } else if response.status() == reqwest::StatusCode::NOT_FOUND {
-            Err(GitHubError::NotFound(url))
+            Err(GitHubError::NotFound(url.to_string()))
         } else {
             Err(GitHubError::NetworkError(format!(
                 "HTTP {}: {}",
@@ -56,16 +167,128 @@ impl GitHubClient {
         }
     }
 
-    pub async fn fetch_file_with_retry(
+    /// Looks up the repository's default branch, caching the answer for the client's lifetime.
+    pub async fn default_branch(&self, owner: &str, repo: &str) -> Result<String, GitHubError> {
+        let cell = self
+            .default_branches
+            .lock()
+            .unwrap()
+            .entry(format!("{}/{}", owner, repo))
+            .or_default()
+            .clone();
+
+        cell.get_or_try_init(|| async {
+            let url = format!("https://api.github.com/repos/{}/{}", owner, repo);
+            let _permit = self.acquire_host(&url).await;
+            let response = self.get(&url).send().await?;
+
+            if response.status().is_success() {
+                let repository: Repository = response.json().await?;
+                Ok(repository.default_branch)
+            } else if response.status() == reqwest::StatusCode::NOT_FOUND {
+                Err(GitHubError::NotFound(format!("{}/{}", owner, repo)))
+            } else {
+                Err(GitHubError::NetworkError(format!(
+                    "HTTP {}: {}",
+                    response.status(),
+                    url
+                )))
+            }
+        })
+        .await
+        .cloned()
+    }
+
+    /// Lists every file (blob) in the repository at `commit` via the Git tree API.
+    pub async fn list_tree(
         &self,
-        source: &DependencySource,
-        max_retries: u32,
+        owner: &str,
+        repo: &str,
+        commit: &str,
+    ) -> Result<Vec<String>, GitHubError> {
+        let url = format!(
+            "https://api.github.com/repos/{}/{}/git/trees/{}?recursive=1",
+            owner, repo, commit
+        );
+        let _permit = self.acquire_host(&url).await;
+        let response = self.get(&url).send().await?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(GitHubError::NotFound(format!("{}/{}@{}", owner, repo, commit)));
+        }
+        if !response.status().is_success() {
+            return Err(GitHubError::NetworkError(format!(
+                "HTTP {}: {}",
+                response.status(),
+                url
+            )));
+        }
+
+        let tree: Tree = response.json().await?;
+        if tree.truncated {
+            return Err(GitHubError::NetworkError(format!(
+                "Tree listing for {}/{} is too large and was truncated by GitHub",
+                owner, repo
+            )));
+        }
+
+        Ok(tree
+            .tree
+            .into_iter()
+            .filter(|entry| entry.kind == "blob")
+            .map(|entry| entry.path)
+            .collect())
+    }
+
+    /// Resolves a tag, branch or abbreviated SHA to the full commit SHA.
+    pub async fn resolve_commit(
+        &self,
+        owner: &str,
+        repo: &str,
+        git_ref: &str,
     ) -> Result<String, GitHubError> {
+        let url = format!(
+            "https://api.github.com/repos/{}/{}/commits/{}",
+            owner, repo, git_ref
+        );
+        let _permit = self.acquire_host(&url).await;
+        let response = self
+            .get(&url)
+            .header("Accept", "application/vnd.github.sha")
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(response.text().await?.trim().to_string())
+        } else if response.status() == reqwest::StatusCode::NOT_FOUND
+            || response.status() == reqwest::StatusCode::UNPROCESSABLE_ENTITY
+        {
+            Err(GitHubError::NotFound(format!(
+                "{}/{}@{}",
+                owner, repo, git_ref
+            )))
+        } else {
+            Err(GitHubError::NetworkError(format!(
+                "HTTP {}: {}",
+                response.status(),
+                url
+            )))
+        }
+    }
+
+    pub async fn fetch_url_with_retry(
+        &self,
+        url: &str,
+        max_retries: u32,
+        limit: Option<u64>,
+    ) -> Result<Vec<u8>, GitHubError> {
         let mut last_error = None;
 
         for attempt in 0..=max_retries {
-            match self.fetch_file(source).await {
+            match self.fetch_url(url, limit).await {
                 Ok(content) => return Ok(content),
+                // 重试也不会改变的错误直接返回
+                Err(e @ (GitHubError::NotFound(_) | GitHubError::TooLarge(..))) => return Err(e),
                 Err(e) => {
                     last_error = Some(e);
                     if attempt < max_retries {
@@ -84,6 +307,12 @@ impl GitHubClient {
     }
 }
 
+fn origin(url: &str) -> Option<String> {
+    reqwest::Url::parse(url)
+        .ok()
+        .map(|url| url.origin().ascii_serialization())
+}
+
 impl Default for GitHubClient {
     fn default() -> Self {
         Self::new(None)
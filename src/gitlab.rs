@@ -0,0 +1,175 @@
+use crate::github::{GitHubClient, GitHubError};
+use crate::utils::{percent_decode, percent_encode};
+use serde::Deserialize;
+
+/// Base URL used when a `gitlab:` dependency does not name its own `host`.
+pub const DEFAULT_HOST: &str = "https://gitlab.com";
+/// Environment variable holding the token sent as `PRIVATE-TOKEN`.
+pub const TOKEN_ENV: &str = "GITLAB_TOKEN";
+const TREE_PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize)]
+struct Project {
+    default_branch: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct TreeEntry {
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// REST API URL of a project; nested group paths are encoded into a single segment.
+fn project_url(host: &str, project: &str) -> String {
+    format!("{}/api/v4/projects/{}", host, percent_encode(project))
+}
+
+/// Raw download URL of `path` at `git_ref` through the repository files API.
+pub fn raw_file_url(host: &str, project: &str, git_ref: &str, path: &str) -> String {
+    format!(
+        "{}/repository/files/{}/raw?ref={}",
+        project_url(host, project),
+        percent_encode(path),
+        percent_encode(git_ref)
+    )
+}
+
+/// Repository path of a `raw_file_url`, or `None` if `url` is not one for this project and ref.
+pub fn file_path(host: &str, project: &str, git_ref: &str, url: &str) -> Option<String> {
+    let prefix = format!("{}/repository/files/", project_url(host, project));
+    let suffix = format!("/raw?ref={}", percent_encode(git_ref));
+    percent_decode(url.strip_prefix(&prefix)?.strip_suffix(&suffix)?)
+}
+
+/// Page showing `path` at `commit` in the GitLab web interface.
+pub fn web_url(host: &str, project: &str, commit: &str, path: &str) -> String {
+    format!("{}/{}/-/blob/{}/{}", host, project, commit, path)
+}
+
+pub async fn default_branch(
+    client: &GitHubClient,
+    host: &str,
+    project: &str,
+) -> Result<String, GitHubError> {
+    let found: Project = client.get_json(&project_url(host, project), project).await?;
+    // 空仓库没有默认分支
+    found
+        .default_branch
+        .ok_or_else(|| GitHubError::NotFound(format!("{} has no default branch", project)))
+}
+
+/// Looks `git_ref` up through `repository/commits/:ref`, which accepts branches, tags and
+/// short SHAs alike.
+pub async fn resolve_commit(
+    client: &GitHubClient,
+    host: &str,
+    project: &str,
+    git_ref: &str,
+) -> Result<String, GitHubError> {
+    let url = format!(
+        "{}/repository/commits/{}",
+        project_url(host, project),
+        percent_encode(git_ref)
+    );
+    let commit: Commit = client
+        .get_json(&url, &format!("{}@{}", project, git_ref))
+        .await?;
+    Ok(commit.id)
+}
+
+/// Every blob in the project at `commit`, requested page by page until a page comes back short.
+pub async fn list_tree(
+    client: &GitHubClient,
+    host: &str,
+    project: &str,
+    commit: &str,
+) -> Result<Vec<String>, GitHubError> {
+    let mut paths = Vec::new();
+    for page in 1.. {
+        let url = format!(
+            "{}/repository/tree?recursive=true&ref={}&per_page={}&page={}",
+            project_url(host, project),
+            percent_encode(commit),
+            TREE_PAGE_SIZE,
+            page
+        );
+        let entries: Vec<TreeEntry> = client
+            .get_json(&url, &format!("{}@{}", project, commit))
+            .await?;
+        let last = entries.len() < TREE_PAGE_SIZE;
+        paths.extend(
+            entries
+                .into_iter()
+                .filter(|entry| entry.kind == "blob")
+                .map(|entry| entry.path),
+        );
+        if last {
+            break;
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn test_api() {
+        let project = "group/sub/project";
+        let api = "/api/v4/projects/group%2Fsub%2Fproject";
+        let commit = "4f5c3a0e2b9d8c7f6e5d4c3b2a1f0e9d8c7b6a5f";
+        let tree = |page: usize| {
+            format!(
+                "{}/repository/tree?recursive=true&ref={}&per_page={}&page={}",
+                api, commit, TREE_PAGE_SIZE, page
+            )
+        };
+        // 第一页正好满一页，第二页不足一页时停止
+        let full: Vec<String> = (0..TREE_PAGE_SIZE)
+            .map(|i| {
+                format!(
+                    r#"{{"id":"9a3f{i}","name":"{i}.md","type":"blob","path":"docs/{i}.md","mode":"100644"}}"#
+                )
+            })
+            .collect();
+        let routes = [
+            (
+                api.to_string(),
+                r#"{"id":42,"path_with_namespace":"group/sub/project","default_branch":"main"}"#
+                    .to_string(),
+            ),
+            (
+                format!("{}/repository/commits/v1.0", api),
+                format!(r#"{{"id":"{}","short_id":"4f5c3a0e","title":"Release 1.0"}}"#, commit),
+            ),
+            (tree(1), format!("[{}]", full.join(","))),
+            (
+                tree(2),
+                r#"[{"id":"c3d4","name":"docs","type":"tree","path":"docs","mode":"040000"},
+                    {"id":"e5f6","name":"README.md","type":"blob","path":"README.md","mode":"100644"}]"#
+                    .to_string(),
+            ),
+        ];
+        let host = testing::serve(&routes).await;
+        let client = GitHubClient::new(None);
+
+        assert_eq!(default_branch(&client, &host, project).await.unwrap(), "main");
+        assert_eq!(resolve_commit(&client, &host, project, "v1.0").await.unwrap(), commit);
+        assert!(matches!(
+            resolve_commit(&client, &host, project, "v9.9").await,
+            Err(GitHubError::NotFound(_))
+        ));
+        let paths = list_tree(&client, &host, project, commit).await.unwrap();
+        assert_eq!(paths.len(), TREE_PAGE_SIZE + 1);
+        assert_eq!(paths[0], "docs/0.md");
+        assert_eq!(paths.last().unwrap(), "README.md");
+    }
+}
@@ -0,0 +1,202 @@
+use crate::dependency::Dependency;
+use thiserror::Error;
+
+const PLACEHOLDERS: &[&str] = &["name", "owner", "repo", "ref", "path", "file"];
+
+/// How fetched files are arranged under the output directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// `owner-repo/path/in/repo.md`; URLs and local paths by file name.
+    #[default]
+    ByRepo,
+    /// `<dependency name>/<path relative to the dependency>`.
+    ByName,
+    /// Every file directly in the output directory.
+    Flat,
+    /// A path template such as `{owner}/{repo}/{ref}/{path}`.
+    Template(String),
+}
+
+#[derive(Debug, Error)]
+pub enum LayoutError {
+    #[error("Unknown placeholder {{{0}}} in layout; expected one of {{name}}, {{owner}}, {{repo}}, {{ref}}, {{path}}, {{file}}")]
+    UnknownPlaceholder(String),
+    #[error("Unterminated placeholder in layout: {0}")]
+    Unterminated(String),
+}
+
+/// Where one dependency's files come from, for placing them in the output directory.
+pub struct Placement<'a> {
+    pub name: &'a str,
+    pub dependency: &'a Dependency,
+    /// Tag or branch the files were fetched at, for `{ref}`.
+    pub git_ref: Option<&'a str>,
+    /// Source directory `{file}` and `to` are relative to.
+    pub root: String,
+    /// Source path of the dependency's own file, or `None` for directories and globs.
+    pub main: Option<String>,
+}
+
+impl Layout {
+    pub fn parse(layout: &str) -> Result<Self, LayoutError> {
+        match layout {
+            "by-repo" => Ok(Layout::ByRepo),
+            "by-name" => Ok(Layout::ByName),
+            "flat" => Ok(Layout::Flat),
+            template => {
+                let mut rest = template;
+                while let Some(start) = rest.find('{') {
+                    let end = rest[start..]
+                        .find('}')
+                        .ok_or_else(|| LayoutError::Unterminated(template.to_string()))?;
+                    let placeholder = &rest[start + 1..start + end];
+                    if !PLACEHOLDERS.contains(&placeholder) {
+                        return Err(LayoutError::UnknownPlaceholder(placeholder.to_string()));
+                    }
+                    rest = &rest[start + end + 1..];
+                }
+                Ok(Layout::Template(template.to_string()))
+            }
+        }
+    }
+}
+
+impl Placement<'_> {
+    /// Output path, relative to the output directory, of the file at `path` in the source.
+    pub fn output_path(&self, layout: &Layout, path: &str) -> String {
+        let file = self.relative_file(path);
+
+        // 依赖自身的 `to` 优先于全局布局
+        if let Some(to) = &self.dependency.to {
+            let placed = match &self.main {
+                Some(main) if main.as_str() == path => to.clone(),
+                Some(_) => join(parent(to), &file),
+                None => join(to, &file),
+            };
+            return normalize(&placed);
+        }
+
+        let (owner, repo) = self.dependency.source.owner_and_repo().unwrap_or_default();
+        let placed = match layout {
+            Layout::ByRepo if owner.is_empty() && repo.is_empty() => path.to_string(),
+            Layout::ByRepo if owner.is_empty() => format!("{}/{}", repo, path),
+            Layout::ByRepo => format!("{}-{}/{}", owner, repo, path),
+            Layout::ByName => join(self.name, &file),
+            Layout::Flat => path.rsplit('/').next().unwrap_or(path).to_string(),
+            Layout::Template(template) => template
+                .replace("{name}", self.name)
+                .replace("{owner}", &owner)
+                .replace("{repo}", &repo)
+                .replace("{ref}", self.git_ref.unwrap_or_default())
+                .replace("{path}", path)
+                .replace("{file}", &file),
+        };
+        normalize(&placed)
+    }
+
+    // 位于依赖根目录之外的文件（例如链接到上级目录）保留完整路径
+    fn relative_file(&self, path: &str) -> String {
+        if self.root.is_empty() {
+            return path.to_string();
+        }
+        if path == self.root {
+            return String::new();
+        }
+        path.strip_prefix(self.root.as_str())
+            .and_then(|rest| rest.strip_prefix('/'))
+            .unwrap_or(path)
+            .to_string()
+    }
+}
+
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+}
+
+fn join(dir: &str, file: &str) -> String {
+    format!("{}/{}", dir, file)
+}
+
+/// Drops empty and `.` segments left by empty placeholders or trailing slashes.
+fn normalize(path: &str) -> String {
+    path.split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dependency(spec: &str) -> Dependency {
+        let value: toml::Value = toml::from_str(&format!("dep = {}", spec)).unwrap();
+        Dependency::parse(&value["dep"]).unwrap()
+    }
+
+    #[test]
+    fn test_builtin_layouts() {
+        let skills =
+            dependency(r#"{ git = "github:anthropics/skills", glob = "skills/*/SKILL.md" }"#);
+        let placement = Placement {
+            name: "skills",
+            dependency: &skills,
+            git_ref: Some("main"),
+            root: "skills".to_string(),
+            main: None,
+        };
+        let path = "skills/pdf/SKILL.md";
+
+        assert_eq!(
+            placement.output_path(&Layout::ByRepo, path),
+            "anthropics-skills/skills/pdf/SKILL.md"
+        );
+        assert_eq!(
+            placement.output_path(&Layout::ByName, path),
+            "skills/pdf/SKILL.md"
+        );
+        assert_eq!(placement.output_path(&Layout::Flat, path), "SKILL.md");
+        assert_eq!(placement.output_path(&Layout::ByName, "skills"), "skills");
+
+        let template = Layout::parse("{owner}/{repo}/{ref}/{path}").unwrap();
+        assert_eq!(
+            placement.output_path(&template, path),
+            "anthropics/skills/main/skills/pdf/SKILL.md"
+        );
+        assert!(Layout::parse("{name}/{version}").is_err());
+        assert!(Layout::parse("{name").is_err());
+    }
+
+    #[test]
+    fn test_to_override() {
+        let api = dependency(r#"{ git = "github:owner/repo/docs/api.md", to = "vendor/api.md" }"#);
+        let placement = Placement {
+            name: "api",
+            dependency: &api,
+            git_ref: None,
+            root: "docs".to_string(),
+            main: Some("docs/api.md".to_string()),
+        };
+
+        assert_eq!(
+            placement.output_path(&Layout::Flat, "docs/api.md"),
+            "vendor/api.md"
+        );
+        assert_eq!(
+            placement.output_path(&Layout::Flat, "docs/img/a.png"),
+            "vendor/img/a.png"
+        );
+
+        let url = dependency(r#"{ url = "https://example.com/a.md" }"#);
+        let placement = Placement {
+            name: "a",
+            dependency: &url,
+            git_ref: None,
+            root: String::new(),
+            main: Some("a.md".to_string()),
+        };
+        let template = Layout::parse("{name}/{ref}/{file}").unwrap();
+        assert_eq!(placement.output_path(&template, "a.md"), "a/a.md");
+        assert_eq!(placement.output_path(&Layout::ByRepo, "a.md"), "a.md");
+    }
+}
@@ -0,0 +1,220 @@
+use pulldown_cmark::{CowStr, Event, LinkType, Parser, Tag, TagEnd};
+use std::ops::Range;
+
+/// Destinations of the links and images in `markdown` that point at other files by path.
+pub fn relative_links(markdown: &str) -> Vec<String> {
+    Parser::new(markdown)
+        .filter_map(|event| match event {
+            Event::Start(Tag::Link { dest_url, .. }) | Event::Start(Tag::Image { dest_url, .. }) => {
+                Some(dest_url.to_string())
+            }
+            _ => None,
+        })
+        .filter(|dest| is_relative(dest))
+        .collect()
+}
+
+/// Replaces relative link and image destinations in `markdown` with whatever `rewrite`
+/// returns for them, leaving the rest of the text untouched.
+pub fn rewrite(markdown: &str, mut rewrite: impl FnMut(&str) -> Option<String>) -> String {
+    let parser = Parser::new(markdown);
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+
+    // 引用式链接的目标写在定义处：[name]: path/to/file.md
+    for (_, definition) in parser.reference_definitions().iter() {
+        if let Some(edit) = locate(markdown, definition.span.clone(), "]:", &definition.dest) {
+            edits.push(edit);
+        }
+    }
+
+    // 内联链接的目标跟在链接文本之后；文本中可能嵌套图片，所以记录其内容结束的位置
+    let mut open: Vec<(Range<usize>, CowStr, usize)> = Vec::new();
+    for (event, range) in parser.into_offset_iter() {
+        match event {
+            Event::Start(Tag::Link {
+                link_type: LinkType::Inline,
+                dest_url,
+                ..
+            })
+            | Event::Start(Tag::Image {
+                link_type: LinkType::Inline,
+                dest_url,
+                ..
+            }) => {
+                let start = range.start;
+                open.push((range, dest_url, start));
+                continue;
+            }
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                if let Some((span, dest, text_end)) = open.pop() {
+                    if let Some(edit) = locate(markdown, text_end..span.end, "](", &dest) {
+                        edits.push(edit);
+                    }
+                }
+            }
+            _ => {}
+        }
+        if let Some((_, _, text_end)) = open.last_mut() {
+            *text_end = range.end.max(*text_end);
+        }
+    }
+
+    edits.sort_by_key(|(range, _)| range.start);
+    edits.dedup_by_key(|(range, _)| range.start);
+
+    let mut output = markdown.to_string();
+    for (range, dest) in edits.into_iter().rev() {
+        if let Some(replacement) = rewrite(&dest) {
+            output.replace_range(range, &replacement);
+        }
+    }
+    output
+}
+
+// 目标紧跟在 `marker` 之后，不在其后的标题中查找
+fn locate(
+    markdown: &str,
+    span: Range<usize>,
+    marker: &str,
+    dest: &str,
+) -> Option<(Range<usize>, String)> {
+    if !is_relative(dest) {
+        return None;
+    }
+    let text = &markdown[span.clone()];
+    let after = text.find(marker)? + marker.len();
+    let offset = after + text[after..].find(dest)?;
+    // 两者之间只能是空白或尖括号
+    if !text[after..offset].trim_start().trim_start_matches('<').is_empty() {
+        return None;
+    }
+    let start = span.start + offset;
+    Some((start..start + dest.len(), dest.to_string()))
+}
+
+/// Path from the directory of `from` to `to`, both relative to the same root.
+pub fn relative_path(from: &str, to: &str) -> String {
+    let from_dirs: Vec<&str> = from.split('/').collect();
+    let from_dirs = &from_dirs[..from_dirs.len() - 1];
+    let to_parts: Vec<&str> = to.split('/').collect();
+
+    let common = from_dirs
+        .iter()
+        .zip(&to_parts)
+        .take_while(|(a, b)| a == b)
+        .count()
+        .min(to_parts.len() - 1);
+
+    let mut parts = vec![".."; from_dirs.len() - common];
+    parts.extend(&to_parts[common..]);
+    parts.join("/")
+}
+
+/// Splits a link into the path and its `#fragment` or `?query` suffix.
+pub fn split_suffix(link: &str) -> (&str, &str) {
+    link.split_at(link.find(['#', '?']).unwrap_or(link.len()))
+}
+
+fn is_relative(dest: &str) -> bool {
+    if dest.is_empty() || dest.starts_with('#') || dest.starts_with("//") {
+        return false;
+    }
+    // 带协议的链接（https:、mailto: 等）不是仓库内的文件
+    match dest.find(':') {
+        Some(colon) => dest[..colon].contains(['/', '?', '#']),
+        None => true,
+    }
+}
+
+/// Resolves `link` against the repository path of the file containing it.
+/// Returns `None` for links that climb out of the repository.
+pub fn resolve(base: &str, link: &str) -> Option<String> {
+    let link = link.split(['#', '?']).next().unwrap_or_default();
+    if link.is_empty() {
+        return None;
+    }
+
+    // 以 / 开头的链接相对于仓库根目录
+    let mut parts: Vec<&str> = if link.starts_with('/') {
+        Vec::new()
+    } else {
+        base.split('/').collect()
+    };
+    parts.pop();
+
+    for component in link.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            component => parts.push(component),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+pub fn is_markdown(path: &str) -> bool {
+    let lower = path.to_ascii_lowercase();
+    lower.ends_with(".md") || lower.ends_with(".markdown")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_links() {
+        let markdown = "See [reference](./reference.md), [docs](https://example.com/x.md),\n\
+                        [section](#usage), [mail](mailto:a@b.c) and ![diagram](img/flow.png).\n\n\
+                        [template]: templates/x.md#top\n\nUse [the template][template].";
+
+        assert_eq!(
+            relative_links(markdown),
+            vec!["./reference.md", "img/flow.png", "templates/x.md#top"]
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let base = "skills/pdf/SKILL.md";
+        assert_eq!(resolve(base, "./reference.md").unwrap(), "skills/pdf/reference.md");
+        assert_eq!(resolve(base, "templates/x.md#top").unwrap(), "skills/pdf/templates/x.md");
+        assert_eq!(resolve(base, "../../README.md").unwrap(), "README.md");
+        assert_eq!(resolve(base, "/docs/guide.md").unwrap(), "docs/guide.md");
+        assert_eq!(resolve(base, "../../../outside.md"), None);
+        assert!(is_markdown("skills/pdf/reference.MD"));
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let markdown = "# [a.md](a.md)\n\n![img](img/x.png \"Title\") [web](https://x.io/a.md)\n\n\
+                        [ref]: ../b.md#top\n\nSee [ref].";
+
+        let rewritten = rewrite(markdown, |dest| Some(format!("<{}>", dest)));
+        assert_eq!(
+            rewritten,
+            "# [a.md](<a.md>)\n\n![img](<img/x.png> \"Title\") [web](https://x.io/a.md)\n\n\
+             [ref]: <../b.md#top>\n\nSee [ref]."
+        );
+        assert_eq!(rewrite(markdown, |_| None), markdown);
+
+        // 标题和链接文本中出现的同名路径保持不变
+        let markdown = "[g](guide.md \"see guide.md\") [![guide.md](guide.md)](guide.md)\n\n\
+                        [r]: guide.md 'guide.md'";
+        assert_eq!(
+            rewrite(markdown, |dest| Some(format!("X/{}", dest))),
+            "[g](X/guide.md \"see guide.md\") [![guide.md](X/guide.md)](X/guide.md)\n\n\
+             [r]: X/guide.md 'guide.md'"
+        );
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(relative_path("o-r/docs/api.md", "o-r/docs/guide.md"), "guide.md");
+        assert_eq!(relative_path("o-r/docs/api.md", "o-r/img/x.png"), "../img/x.png");
+        assert_eq!(relative_path("a.md", "o-r/b.md"), "o-r/b.md");
+        assert_eq!(relative_path("o-r/a/b.md", "o-r/a"), "../a");
+        assert_eq!(split_suffix("a.md#top"), ("a.md", "#top"));
+    }
+}
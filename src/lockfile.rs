@@ -0,0 +1,359 @@
+use crate::dependency::{Dependency, DependencySource, LinkMode};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub const LOCK_FILE_NAME: &str = "markdown.lock";
+// 2：每个包记录多个文件（`[[package.file]]`），支持本地路径依赖
+const LOCK_FILE_VERSION: u32 = 2;
+const LOCK_FILE_HEADER: &str = "# This file is automatically generated by cardo.\n\
+# It is not intended for manual editing.\n";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LockFile {
+    pub version: u32,
+    #[serde(default, rename = "package", skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LockedPackage {
+    pub name: String,
+    /// Dependency spec as written in markdown.toml, used to detect manifest edits.
+    pub source: String,
+    /// Tag, branch or revision the commit was resolved from.
+    #[serde(default, rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, rename = "file")]
+    pub files: Vec<LockedFile>,
+    /// How links were rewritten when the files were written.
+    #[serde(default, skip_serializing_if = "LinkMode::is_default")]
+    pub links: LinkMode,
+    /// Names of the packages this one pulled in through its own markdown.toml.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    /// Checksum the upstream markdown.toml declared for a package pulled in through it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LockedFile {
+    /// Destination relative to the output directory.
+    pub path: String,
+    /// Where the content was read from: a pinned URL or a local path.
+    pub url: String,
+    pub sha256: String,
+    /// SHA-256 of the file as written, when rewriting its links changed the content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rewritten: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum LockError {
+    #[error("Failed to read lock file: {0}")]
+    ReadError(#[from] std::io::Error),
+    #[error("Failed to parse lock file: {0}")]
+    ParseError(#[from] toml::de::Error),
+    #[error("Failed to serialize lock file: {0}")]
+    SerializeError(#[from] toml::ser::Error),
+    #[error("Unsupported lock file version: {0}; delete markdown.lock and run `cardo fetch`")]
+    UnsupportedVersion(u32),
+}
+
+impl Default for LockFile {
+    fn default() -> Self {
+        Self {
+            version: LOCK_FILE_VERSION,
+            packages: Vec::new(),
+        }
+    }
+}
+
+impl LockFile {
+    pub fn new(mut packages: Vec<LockedPackage>) -> Self {
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Self {
+            version: LOCK_FILE_VERSION,
+            packages,
+        }
+    }
+
+    /// The lock file lives next to the manifest it was generated from.
+    pub fn path_for(config_file: &str) -> PathBuf {
+        Path::new(config_file).with_file_name(LOCK_FILE_NAME)
+    }
+
+    /// Loads the lock file, returning an empty one if it does not exist yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LockError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let lock: LockFile = toml::from_str(&content)?;
+        if lock.version != LOCK_FILE_VERSION {
+            return Err(LockError::UnsupportedVersion(lock.version));
+        }
+        Ok(lock)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), LockError> {
+        let content = format!("{}{}", LOCK_FILE_HEADER, toml::to_string_pretty(self)?);
+        crate::utils::write_atomic(path.as_ref(), content.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LockedPackage> {
+        self.packages.iter().find(|p| p.name == name)
+    }
+
+    /// Describes every way the lock disagrees with the manifest; empty when it is up to date.
+    pub fn outdated(&self, dependencies: &HashMap<String, Dependency>) -> Vec<String> {
+        let mut names: Vec<_> = dependencies.keys().collect();
+        names.sort();
+
+        let mut problems = Vec::new();
+        for name in names {
+            let source = dependencies[name].source.to_string();
+            match self.get(name) {
+                None => problems.push(format!("`{}` is missing from markdown.lock", name)),
+                Some(locked) if locked.source != source => problems.push(format!(
+                    "`{}` changed in markdown.toml: locked {}, now {}",
+                    name, locked.source, source
+                )),
+                Some(_) => {}
+            }
+        }
+        let reachable = self.reachable(dependencies.keys());
+        for package in &self.packages {
+            if !reachable.contains(package.name.as_str()) {
+                problems.push(format!(
+                    "`{}` is in markdown.lock but not in markdown.toml",
+                    package.name
+                ));
+            }
+        }
+        problems
+    }
+
+    /// Names of the packages reachable from `roots` through recorded dependencies.
+    pub fn reachable<'a>(&'a self, roots: impl IntoIterator<Item = &'a String>) -> HashSet<&'a str> {
+        let mut reachable = HashSet::new();
+        let mut pending: Vec<&str> = roots.into_iter().map(String::as_str).collect();
+        while let Some(name) = pending.pop() {
+            if reachable.insert(name) {
+                if let Some(package) = self.get(name) {
+                    pending.extend(package.dependencies.iter().map(String::as_str));
+                }
+            }
+        }
+        reachable
+    }
+
+    /// Packages that list `name` among their dependencies.
+    pub fn dependents(&self, name: &str) -> Vec<&str> {
+        self.packages
+            .iter()
+            .filter(|p| p.dependencies.iter().any(|d| d == name))
+            .map(|p| p.name.as_str())
+            .collect()
+    }
+}
+
+impl LockedPackage {
+    /// Short human-readable version, e.g. `v1.0.0@1a2b3c4` or `sha256:1a2b3c4d5e6f`.
+    pub fn version_label(&self) -> String {
+        match (&self.git_ref, &self.commit) {
+            (Some(git_ref), Some(commit)) if commit.starts_with(git_ref.as_str()) => {
+                short_sha(commit).to_string()
+            }
+            (Some(git_ref), Some(commit)) => format!("{}@{}", git_ref, short_sha(commit)),
+            (None, Some(commit)) => short_sha(commit).to_string(),
+            _ => {
+                let digest = self.digest();
+                format!("sha256:{}", &digest[..digest.len().min(12)])
+            }
+        }
+    }
+
+    /// Checksum of the package content; for multi-file packages, a hash over every file
+    /// and its path in the source, so that `out-dir`, `layout` and `to` do not change it.
+    pub fn digest(&self) -> String {
+        match self.files.as_slice() {
+            [file] => file.sha256.clone(),
+            files => {
+                let source = DependencySource::from_id(&self.source).ok();
+                let joined: Vec<String> = files
+                    .iter()
+                    .map(|f| {
+                        // 仓库中的文件按仓库内路径，其他文件按 URL 或本地路径
+                        let path = source
+                            .as_ref()
+                            .zip(self.commit.as_deref())
+                            .and_then(|(source, commit)| source.repo_file_path(commit, &f.url))
+                            .unwrap_or_else(|| f.url.clone());
+                        format!("{} {}", f.sha256, path)
+                    })
+                    .collect();
+                crate::utils::sha256_hex(joined.join("\n").as_bytes())
+            }
+        }
+    }
+}
+
+fn short_sha(sha: &str) -> &str {
+    &sha[..sha.len().min(7)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_file_roundtrip() {
+        let lock = LockFile::new(vec![
+            LockedPackage {
+                name: "b".to_string(),
+                source: "https://example.com/b.md".to_string(),
+                git_ref: None,
+                commit: None,
+                files: vec![LockedFile {
+                    path: "b.md".to_string(),
+                    url: "https://example.com/b.md".to_string(),
+                    sha256: "00".to_string(),
+                    rewritten: None,
+                }],
+                links: LinkMode::default(),
+                dependencies: Vec::new(),
+                sha256: None,
+            },
+            LockedPackage {
+                name: "a".to_string(),
+                source: "github:owner/repo/a.md?branch=main".to_string(),
+                git_ref: Some("main".to_string()),
+                commit: Some("0123456789abcdef".to_string()),
+                files: vec![LockedFile {
+                    path: "owner-repo/a.md".to_string(),
+                    url: "https://raw.githubusercontent.com/owner/repo/0123456789abcdef/a.md"
+                        .to_string(),
+                    sha256: "ff".to_string(),
+                    rewritten: None,
+                }],
+                links: LinkMode::default(),
+                dependencies: vec!["b".to_string()],
+                sha256: None,
+            },
+        ]);
+
+        let content = toml::to_string_pretty(&lock).unwrap();
+        let parsed: LockFile = toml::from_str(&content).unwrap();
+        assert_eq!(parsed.packages[0].name, "a");
+        assert_eq!(parsed.get("b"), lock.get("b"));
+        assert!(parsed.get("b").unwrap().commit.is_none());
+        assert_eq!(parsed.get("a").unwrap().version_label(), "main@0123456");
+        assert_eq!(parsed.get("b").unwrap().version_label(), "sha256:00");
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        // 版本 1 的锁文件把文件字段直接写在包里，按新格式解析会丢掉它们
+        let path = std::env::temp_dir().join(format!("cardo-lock-{}.lock", std::process::id()));
+        fs::write(
+            &path,
+            "version = 1\n\n[[package]]\nname = \"a\"\nsource = \"https://example.com/a.md\"\n\
+             url = \"https://example.com/a.md\"\nsha256 = \"00\"\npath = \"a.md\"\n",
+        )
+        .unwrap();
+        assert!(matches!(LockFile::load(&path), Err(LockError::UnsupportedVersion(1))));
+
+        LockFile::default().save(&path).unwrap();
+        assert_eq!(LockFile::load(&path).unwrap().version, LOCK_FILE_VERSION);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_outdated() {
+        let locked = |name: &str, source: &str| LockedPackage {
+            name: name.to_string(),
+            source: source.to_string(),
+            git_ref: None,
+            commit: None,
+            files: Vec::new(),
+            links: LinkMode::default(),
+            dependencies: Vec::new(),
+            sha256: None,
+        };
+        let lock = LockFile::new(vec![
+            locked("a", "https://example.com/a.md"),
+            locked("b", "https://example.com/old.md"),
+            locked("stale", "https://example.com/stale.md"),
+        ]);
+
+        let mut dependencies = HashMap::new();
+        for (name, url) in [
+            ("a", "https://example.com/a.md"),
+            ("b", "https://example.com/b.md"),
+            ("c", "https://example.com/c.md"),
+        ] {
+            let value = toml::Value::String(url.to_string());
+            dependencies.insert(name.to_string(), Dependency::parse(&value).unwrap());
+        }
+
+        let problems = lock.outdated(&dependencies);
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].starts_with("`b` changed"));
+        assert!(problems[1].starts_with("`c` is missing"));
+        assert!(problems[2].starts_with("`stale` is in markdown.lock"));
+
+        // 通过其他依赖间接引入的记录不算过期
+        let mut lock = lock;
+        lock.packages[0].dependencies.push("stale".to_string());
+        assert_eq!(lock.outdated(&dependencies).len(), 2);
+        assert_eq!(lock.dependents("stale"), vec!["a"]);
+
+        dependencies.remove("b");
+        dependencies.remove("c");
+        assert_eq!(lock.outdated(&dependencies).len(), 1);
+    }
+
+    #[test]
+    fn test_digest_ignores_output_paths() {
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let package = |paths: [&str; 2]| LockedPackage {
+            name: "docs".to_string(),
+            source: "github:owner/repo/docs/".to_string(),
+            git_ref: Some("main".to_string()),
+            commit: Some(commit.to_string()),
+            files: ["docs/a.md", "docs/b.md"]
+                .iter()
+                .zip(paths)
+                .map(|(source_path, path)| LockedFile {
+                    path: path.to_string(),
+                    url: format!(
+                        "https://raw.githubusercontent.com/owner/repo/{}/{}",
+                        commit, source_path
+                    ),
+                    sha256: "ff".to_string(),
+                    rewritten: None,
+                })
+                .collect(),
+            links: LinkMode::default(),
+            dependencies: Vec::new(),
+            sha256: None,
+        };
+
+        let by_repo = package(["owner-repo/docs/a.md", "owner-repo/docs/b.md"]);
+        let flat = package(["a.md", "b.md"]);
+        assert_eq!(by_repo.digest(), flat.digest());
+        assert_eq!(
+            by_repo.digest(),
+            crate::utils::sha256_hex(b"ff docs/a.md\nff docs/b.md")
+        );
+    }
+}
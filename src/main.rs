@@ -1,42 +1,112 @@
+mod bitbucket;
+mod cache;
 mod cli;
 mod config;
 mod dependency;
 mod fetcher;
+mod flock;
+mod gist;
+mod git;
+mod gitea;
 mod github;
+mod gitlab;
+mod layout;
+mod links;
+mod lockfile;
+mod managed;
+mod plan;
+#[cfg(test)]
+mod testing;
 mod utils;
 
 use anyhow::{Context, Result};
-use cli::{Cli, Commands};
+use cli::{CacheCommand, Cli, Commands};
 use config::ConfigError;
+use lockfile::LockFile;
+use std::collections::HashMap;
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use clap::Parser;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::try_parse()?;
 
+    // 会写入项目的命令互斥执行
+    let _lock = match cli.command {
+        Commands::Fetch { .. }
+        | Commands::Update { .. }
+        | Commands::Add { .. }
+        | Commands::Remove { .. }
+        | Commands::Clean { .. } => lock_project(!cli.no_wait)?,
+        _ => None,
+    };
+
     match cli.command {
         Commands::Init { name } => {
             handle_init(name).await?;
         }
-        Commands::Fetch { force } => {
-            handle_fetch(force).await?;
+        Commands::Fetch {
+            force,
+            jobs,
+            offline,
+            locked,
+            frozen,
+            no_prune,
+        } => {
+            handle_fetch(force, jobs, offline || frozen, locked || frozen, !no_prune).await?;
+        }
+        Commands::Update {
+            names,
+            precise,
+            force,
+            jobs,
+            no_prune,
+            keep_going,
+        } => {
+            handle_update(names, precise, force, jobs, !no_prune, keep_going).await?;
+        }
+        Commands::Add {
+            name,
+            spec,
+            tag,
+            branch,
+            rev,
+        } => {
+            handle_add(name, spec, tag, branch, rev).await?;
         }
-        Commands::Update { force } => {
-            handle_update(force).await?;
+        Commands::Remove { name } => {
+            handle_remove(name).await?;
         }
         Commands::List => {
             handle_list().await?;
         }
-        Commands::Clean => {
-            handle_clean().await?;
+        Commands::Clean { dry_run, all } => {
+            handle_clean(dry_run, all).await?;
+        }
+        Commands::Hash { name } => {
+            handle_hash(name).await?;
+        }
+        Commands::Cache { command } => {
+            handle_cache(command)?;
         }
     }
 
     Ok(())
 }
 
+/// Takes the project lock, or returns `None` outside a project.
+fn lock_project(wait: bool) -> Result<Option<flock::ProjectLock>> {
+    let Some(config_file) = utils::find_config_file() else {
+        return Ok(None);
+    };
+    let lock = flock::ProjectLock::acquire(&utils::project_dir(&config_file), wait, |pid| match pid {
+        Some(pid) => eprintln!("waiting for lock held by pid {}", pid),
+        None => eprintln!("waiting for lock held by another cardo process"),
+    })?;
+    Ok(Some(lock))
+}
+
 async fn handle_init(name: Option<String>) -> Result<()> {
     let config_file = "markdown.toml";
 
@@ -60,7 +130,14 @@ async fn handle_init(name: Option<String>) -> Result<()> {
     Ok(())
 }
 
-async fn handle_fetch(force: bool) -> Result<()> {
+// 退出码，便于 CI 区分“需要更新锁文件”和“网络不可用”
+const EXIT_FAILURE: i32 = 1;
+const EXIT_LOCK_OUTDATED: i32 = 2;
+const EXIT_NETWORK: i32 = 3;
+
+type Dependencies = HashMap<String, dependency::Dependency>;
+
+fn load_dependencies() -> Result<(String, config::MarkdownConfig, Dependencies)> {
     let config_file = utils::find_config_file()
         .ok_or_else(|| ConfigError::NotFound("markdown.toml".to_string()))?;
 
@@ -71,18 +148,166 @@ async fn handle_fetch(force: bool) -> Result<()> {
         .parse_dependencies()
         .context("Failed to parse dependencies")?;
 
+    Ok((config_file, config, dependencies))
+}
+
+// 命令行参数优先于 markdown.toml 中的 [fetch] 配置
+fn resolve_jobs(cli_jobs: Option<usize>, config: &config::MarkdownConfig) -> usize {
+    cli_jobs
+        .or_else(|| config.fetch.as_ref().and_then(|f| f.jobs))
+        .unwrap_or(fetcher::DEFAULT_JOBS)
+}
+
+fn resolve_max_asset_size(config: &config::MarkdownConfig) -> u64 {
+    config
+        .fetch
+        .as_ref()
+        .and_then(|f| f.max_asset_size)
+        .unwrap_or(fetcher::DEFAULT_MAX_ASSET_SIZE)
+}
+
+/// Creates a fetcher writing to the manifest's `out-dir` with its configured layout.
+fn new_fetcher(
+    config_file: &str,
+    config: &config::MarkdownConfig,
+    github_token: Option<String>,
+) -> Result<fetcher::Fetcher> {
+    let layout = config.layout().context("Failed to parse markdown.toml")?;
+    Ok(fetcher::Fetcher::new(
+        utils::output_dir(config_file, config.out_dir()),
+        utils::project_dir(config_file),
+        github_token,
+    )
+    .with_layout(layout))
+}
+
+/// Tokens from the environment, plus the per-host ones named in `[fetch] tokens`.
+fn forge_tokens(config: &config::MarkdownConfig, dependencies: &Dependencies) -> fetcher::ForgeTokens {
+    let hosts = config
+        .fetch
+        .iter()
+        .flat_map(|fetch| &fetch.tokens)
+        .filter_map(|(host, var)| {
+            let token = env::var(var).ok()?;
+            Some((host.trim_end_matches('/').to_string(), token))
+        })
+        .collect();
+    fetcher::ForgeTokens {
+        gitlab: env::var(gitlab::TOKEN_ENV).ok(),
+        gitea: env::var(gitea::TOKEN_ENV).ok(),
+        bitbucket: bitbucket_credentials(),
+        hosts,
+        declared: dependencies
+            .values()
+            .filter_map(|dependency| dependency.source.host())
+            .map(str::to_string)
+            .collect(),
+    }
+}
+
+/// `Authorization` value for Bitbucket: a bearer token, or else app password credentials.
+fn bitbucket_credentials() -> Option<String> {
+    if let Ok(token) = env::var(bitbucket::TOKEN_ENV) {
+        return Some(format!("Bearer {}", token));
+    }
+    let username = env::var(bitbucket::USERNAME_ENV).ok()?;
+    let app_password = env::var(bitbucket::APP_PASSWORD_ENV).ok()?;
+    Some(bitbucket::basic_auth(&username, &app_password))
+}
+
+/// Fetches `dependencies` honoring the `pinned` lock entries and rewrites markdown.lock.
+async fn fetch_and_lock(
+    config_file: &str,
+    config: &config::MarkdownConfig,
+    dependencies: &Dependencies,
+    pinned: &LockFile,
+    previous_lock: &LockFile,
+    options: &fetcher::FetchOptions,
+) -> Result<fetcher::FetchReport> {
+    utils::ensure_output_dir(&utils::output_dir(config_file, config.out_dir()))?;
+
+    let fetcher = new_fetcher(config_file, config, env::var("GITHUB_TOKEN").ok())?
+        .with_tokens(forge_tokens(config, dependencies));
+    let report = fetcher.fetch_all(dependencies, pinned, options).await?;
+    if !report.applied {
+        return Ok(report);
+    }
+
+    // 下载失败的依赖保留原有的锁定记录
+    let lock = LockFile::new(
+        report
+            .results
+            .iter()
+            .filter_map(|r| {
+                r.locked
+                    .clone()
+                    .or_else(|| previous_lock.get(&r.name).cloned())
+            })
+            .collect(),
+    );
+    lock.save(LockFile::path_for(config_file))
+        .context("Failed to write markdown.lock")?;
+
+    Ok(report)
+}
+
+fn print_warnings(result: &fetcher::FetchResult) {
+    for warning in &result.warnings {
+        println!("    warning: {}", warning);
+    }
+}
+
+fn print_pruned(pruned: &[String]) {
+    if !pruned.is_empty() {
+        println!("\nPruned files no dependency writes any more:");
+        for path in pruned {
+            println!("  - {}", path);
+        }
+    }
+}
+
+async fn handle_fetch(
+    force: bool,
+    jobs: Option<usize>,
+    offline: bool,
+    locked: bool,
+    prune: bool,
+) -> Result<()> {
+    let (config_file, config, dependencies) = load_dependencies()?;
+
+    let lock_path = LockFile::path_for(&config_file);
+    let lock = LockFile::load(&lock_path).context("Failed to load markdown.lock")?;
+
+    if locked {
+        let problems = lock.outdated(&dependencies);
+        if !problems.is_empty() {
+            eprintln!("markdown.lock needs to be updated but --locked was given:");
+            for problem in &problems {
+                eprintln!("  {}", problem);
+            }
+            eprintln!("Run `cardo update` to refresh it.");
+            std::process::exit(EXIT_LOCK_OUTDATED);
+        }
+    }
+
     if dependencies.is_empty() {
         println!("No dependencies found in markdown.toml");
         return Ok(());
     }
 
-    utils::ensure_output_dir("markdowns")?;
-
-    let github_token = env::var("GITHUB_TOKEN").ok();
-    let fetcher = fetcher::Fetcher::new("markdowns".to_string(), github_token);
+    let options = fetcher::FetchOptions {
+        force,
+        jobs: resolve_jobs(jobs, &config),
+        max_asset_size: resolve_max_asset_size(&config),
+        offline,
+        locked,
+        prune,
+        ..Default::default()
+    };
 
     println!("Fetching {} dependencies...", dependencies.len());
-    let results = fetcher.fetch_all(&dependencies, force).await?;
+    let report = fetch_and_lock(&config_file, &config, &dependencies, &lock, &lock, &options).await?;
+    let results = report.results;
 
     let mut success_count = 0;
     let mut fail_count = 0;
@@ -90,7 +315,16 @@ async fn handle_fetch(force: bool) -> Result<()> {
     for result in &results {
         if result.success {
             success_count += 1;
-            println!("  ✓ {} -> {}", result.name, result.path);
+            match &result.locked {
+                Some(locked) => println!(
+                    "  ✓ {} -> {} ({})",
+                    result.name,
+                    result.path,
+                    locked.version_label()
+                ),
+                None => println!("  ✓ {} -> {}", result.name, result.path),
+            }
+            print_warnings(result);
         } else {
             fail_count += 1;
             println!(
@@ -101,30 +335,235 @@ async fn handle_fetch(force: bool) -> Result<()> {
         }
     }
 
+    print_pruned(&report.pruned);
     println!("\nSummary: {} succeeded, {} failed", success_count, fail_count);
 
     if fail_count > 0 {
-        std::process::exit(1);
+        std::process::exit(exit_code(&results));
     }
 
     Ok(())
 }
 
-async fn handle_update(force: bool) -> Result<()> {
-    // Update 和 Fetch 目前功能相同
-    handle_fetch(force).await
+// 同时存在多种失败时，锁文件过期优先于网络错误
+fn exit_code(results: &[fetcher::FetchResult]) -> i32 {
+    let kinds: Vec<_> = results.iter().filter_map(|r| r.failure).collect();
+    if kinds.contains(&fetcher::FailureKind::LockOutdated) {
+        EXIT_LOCK_OUTDATED
+    } else if kinds.contains(&fetcher::FailureKind::Network) {
+        EXIT_NETWORK
+    } else {
+        EXIT_FAILURE
+    }
 }
 
-async fn handle_list() -> Result<()> {
+async fn handle_update(
+    names: Vec<String>,
+    precise: Option<String>,
+    force: bool,
+    jobs: Option<usize>,
+    prune: bool,
+    keep_going: bool,
+) -> Result<()> {
+    let (config_file, config, dependencies) = load_dependencies()?;
+
+    for name in &names {
+        if !dependencies.contains_key(name) {
+            anyhow::bail!("Dependency `{}` not found in markdown.toml", name);
+        }
+    }
+
+    let mut options = fetcher::FetchOptions {
+        force,
+        jobs: resolve_jobs(jobs, &config),
+        max_asset_size: resolve_max_asset_size(&config),
+        prune,
+        all_or_nothing: !keep_going,
+        ..Default::default()
+    };
+    if let Some(rev) = precise {
+        if names.len() != 1 {
+            anyhow::bail!("--precise requires exactly one dependency name");
+        }
+        if !matches!(
+            dependencies.get(&names[0]).map(|d| &d.source),
+            Some(
+                dependency::DependencySource::GitHub { .. }
+                    | dependency::DependencySource::GitLab { .. }
+                    | dependency::DependencySource::Gitea { .. }
+                    | dependency::DependencySource::Bitbucket { .. }
+                    | dependency::DependencySource::Gist { .. }
+                    | dependency::DependencySource::Git { .. }
+            )
+        ) {
+            anyhow::bail!("--precise is only supported for git dependencies");
+        }
+        options.precise.insert(names[0].clone(), rev);
+    }
+
+    if dependencies.is_empty() {
+        println!("No dependencies found in markdown.toml");
+        return Ok(());
+    }
+
+    let lock_path = LockFile::path_for(&config_file);
+    let previous_lock = LockFile::load(&lock_path).context("Failed to load markdown.lock")?;
+
+    // 未指定名称时重新解析全部依赖，否则只放开指定的依赖
+    let pinned = LockFile::new(
+        previous_lock
+            .packages
+            .iter()
+            .filter(|p| !names.is_empty() && !names.contains(&p.name))
+            .cloned()
+            .collect(),
+    );
+
+    println!("Updating dependencies...");
+    let report =
+        fetch_and_lock(&config_file, &config, &dependencies, &pinned, &previous_lock, &options).await?;
+    let results = report.results;
+
+    let mut updated_count = 0;
+    let mut fail_count = 0;
+
+    for result in &results {
+        match (&result.locked, previous_lock.get(&result.name)) {
+            (None, _) => {
+                fail_count += 1;
+                println!(
+                    "  ✗ {}: {}",
+                    result.name,
+                    result.error.as_deref().unwrap_or("Unknown error")
+                );
+            }
+            // 整体放弃时不报告未生效的更新
+            _ if !report.applied => {}
+            (Some(new), Some(old)) if new.commit == old.commit && new.files == old.files => {}
+            (Some(new), Some(old)) => {
+                updated_count += 1;
+                println!(
+                    "  ✓ {}: {} -> {}",
+                    result.name,
+                    old.version_label(),
+                    new.version_label()
+                );
+            }
+            (Some(new), None) => {
+                updated_count += 1;
+                println!("  + {}: {}", result.name, new.version_label());
+            }
+        }
+        if report.applied {
+            print_warnings(result);
+        }
+    }
+
+    for old in &previous_lock.packages {
+        if report.applied && !results.iter().any(|r| r.name == old.name) {
+            println!("  - {}: {}", old.name, old.version_label());
+        }
+    }
+
+    if updated_count == 0 && fail_count == 0 {
+        println!("All dependencies are up to date");
+    }
+    print_pruned(&report.pruned);
+    if !report.applied {
+        println!(
+            "\nNothing was changed because some dependencies failed; \
+             use --keep-going to apply the others"
+        );
+    }
+    println!("\nSummary: {} updated, {} failed", updated_count, fail_count);
+
+    if fail_count > 0 {
+        std::process::exit(exit_code(&results));
+    }
+
+    Ok(())
+}
+
+async fn handle_add(
+    name: String,
+    spec: String,
+    tag: Option<String>,
+    branch: Option<String>,
+    rev: Option<String>,
+) -> Result<()> {
     let config_file = utils::find_config_file()
         .ok_or_else(|| ConfigError::NotFound("markdown.toml".to_string()))?;
 
-    let config = config::MarkdownConfig::from_file(&config_file)
-        .context("Failed to load markdown.toml")?;
+    // 指定版本时使用表格式：{ git = "...", tag = "..." }
+    let version = [("tag", tag), ("branch", branch), ("rev", rev)]
+        .into_iter()
+        .find_map(|(key, value)| value.map(|v| (key, v)));
+    let value: toml_edit::Value = match &version {
+        Some((key, version)) => {
+            let mut table = toml_edit::InlineTable::new();
+            // gist 的表格式：{ gist = "<id>", file = "...", rev = "..." }
+            match spec.strip_prefix("gist:").and_then(|gist| gist.split_once('/')) {
+                Some((id, file)) => {
+                    table.insert("gist", id.into());
+                    table.insert("file", file.into());
+                }
+                None => {
+                    table.insert("git", spec.as_str().into());
+                }
+            }
+            table.insert(*key, version.as_str().into());
+            table.into()
+        }
+        None => spec.as_str().into(),
+    };
 
-    let dependencies = config
-        .parse_dependencies()
-        .context("Failed to parse dependencies")?;
+    let is_url = spec.starts_with("http://") || spec.starts_with("https://");
+    if version.is_some() && is_url {
+        anyhow::bail!("--tag, --branch and --rev only apply to git dependencies");
+    }
+
+    // 写入前先用与 fetch 相同的规则校验依赖声明
+    let parsed: toml::Table = toml::from_str(&format!("dependency = {}", value))?;
+    dependency::Dependency::parse(&parsed["dependency"])
+        .with_context(|| format!("Invalid dependency `{}`", spec))?;
+
+    if config::add_dependency(&config_file, &name, value)? {
+        println!("Updated {} in {}", name, config_file);
+    } else {
+        println!("Added {} to {}", name, config_file);
+    }
+
+    Ok(())
+}
+
+async fn handle_remove(name: String) -> Result<()> {
+    let (config_file, config, dependencies) = load_dependencies()?;
+
+    let dependency = dependencies
+        .get(&name)
+        .ok_or_else(|| anyhow::anyhow!("Dependency `{}` not found in markdown.toml", name))?;
+
+    let lock_path = LockFile::path_for(&config_file);
+    let mut lock = LockFile::load(&lock_path).context("Failed to load markdown.lock")?;
+
+    let fetcher = new_fetcher(&config_file, &config, None)?;
+    let removed = fetcher.remove(&name, dependency, lock.get(&name)).await?;
+    for path in &removed {
+        println!("  - {}", path);
+    }
+
+    config::remove_dependency(&config_file, &name)?;
+    if lock.get(&name).is_some() {
+        lock.packages.retain(|p| p.name != name);
+        lock.save(&lock_path).context("Failed to write markdown.lock")?;
+    }
+
+    println!("Removed {} from {}", name, config_file);
+    Ok(())
+}
+
+async fn handle_list() -> Result<()> {
+    let (config_file, _, dependencies) = load_dependencies()?;
 
     if dependencies.is_empty() {
         println!("No dependencies found in markdown.toml");
@@ -132,37 +571,150 @@ async fn handle_list() -> Result<()> {
     }
 
     println!("Dependencies:");
-    for (name, source) in &dependencies {
-        match source {
-            dependency::DependencySource::GitHub {
-                owner,
-                repo,
-                path,
-                version,
-            } => {
+    for (name, dependency) in &dependencies {
+        match &dependency.source {
+            source @ (dependency::DependencySource::GitHub { version, .. }
+            | dependency::DependencySource::GitLab { version, .. }
+            | dependency::DependencySource::Gitea { version, .. }
+            | dependency::DependencySource::Bitbucket { version, .. }
+            | dependency::DependencySource::Gist { version, .. }
+            | dependency::DependencySource::Git { version, .. }) => {
                 let version_str = match version {
                     Some(dependency::Version::Tag(t)) => format!("tag:{}", t),
                     Some(dependency::Version::Branch(b)) => format!("branch:{}", b),
                     Some(dependency::Version::Commit(c)) => format!("commit:{}", c),
-                    None => "main".to_string(),
+                    // gist 没有分支，未固定时跟随最新修订
+                    None if matches!(source, dependency::DependencySource::Gist { .. }) => {
+                        "latest revision".to_string()
+                    }
+                    None => "default branch".to_string(),
                 };
-                println!(
-                    "  {}: github:{}/{}/{} ({})",
-                    name, owner, repo, path, version_str
-                );
+                println!("  {}: {} ({})", name, source.identity(), version_str);
             }
             dependency::DependencySource::Url(url) => {
                 println!("  {}: {}", name, url);
             }
+            dependency::DependencySource::Local { path, symlink } => {
+                let mode = if *symlink { "symlink" } else { "copy" };
+                println!("  {}: path:{} ({})", name, path, mode);
+            }
+        }
+    }
+
+    // 间接依赖只记录在锁文件中
+    let lock = LockFile::load(LockFile::path_for(&config_file))
+        .context("Failed to load markdown.lock")?;
+    let reachable = lock.reachable(dependencies.keys());
+    let indirect: Vec<_> = lock
+        .packages
+        .iter()
+        .filter(|p| reachable.contains(p.name.as_str()) && !dependencies.contains_key(&p.name))
+        .collect();
+    if !indirect.is_empty() {
+        println!("\nIndirect dependencies:");
+        for package in indirect {
+            println!(
+                "  {}: {} ({}, via {})",
+                package.name,
+                package.source,
+                package.version_label(),
+                lock.dependents(&package.name).join(", ")
+            );
         }
     }
 
     Ok(())
 }
 
-async fn handle_clean() -> Result<()> {
-    let fetcher = fetcher::Fetcher::new("markdowns".to_string(), None);
-    fetcher.clean().await?;
-    println!("Cleaned markdowns/ directory");
+async fn handle_clean(dry_run: bool, all: bool) -> Result<()> {
+    // 没有 markdown.toml 时清理默认目录
+    let (output_dir, lock) = match utils::find_config_file() {
+        Some(config_file) => {
+            let config = config::MarkdownConfig::from_file(&config_file)
+                .context("Failed to load markdown.toml")?;
+            let lock = LockFile::load(LockFile::path_for(&config_file))
+                .context("Failed to load markdown.lock")?;
+            (utils::output_dir(&config_file, config.out_dir()), lock)
+        }
+        None => (config::DEFAULT_OUT_DIR.to_string(), LockFile::default()),
+    };
+    let fetcher = fetcher::Fetcher::new(output_dir.clone(), PathBuf::from("."), None);
+    let report = fetcher.clean(&lock, all, dry_run).await?;
+
+    if dry_run {
+        println!("Would remove:");
+    }
+    for path in &report.removed {
+        println!("  - {}", path);
+    }
+    if !report.kept.is_empty() {
+        eprintln!("warning: leaving files that cardo did not write (use --all to remove them too):");
+        for path in &report.kept {
+            eprintln!("  {}", path);
+        }
+    }
+    if !dry_run {
+        println!("Cleaned {}/ directory", output_dir);
+    }
+    Ok(())
+}
+
+async fn handle_hash(name: String) -> Result<()> {
+    let (config_file, _, dependencies) = load_dependencies()?;
+
+    let dependency = dependencies
+        .get(&name)
+        .ok_or_else(|| anyhow::anyhow!("Dependency `{}` not found in markdown.toml", name))?;
+
+    let lock = LockFile::load(LockFile::path_for(&config_file))
+        .context("Failed to load markdown.lock")?;
+    let locked = lock
+        .get(&name)
+        .filter(|l| l.source == dependency.source.to_string())
+        .ok_or_else(|| {
+            anyhow::anyhow!("`{}` has not been fetched yet; run `cardo fetch` first", name)
+        })?;
+
+    let digest = locked.digest();
+    println!("sha256 = \"{}\"", digest);
+    if let Some(sri) = utils::sri_from_hex(&digest) {
+        println!("integrity = \"{}\"", sri);
+    }
+
+    Ok(())
+}
+
+fn handle_cache(command: CacheCommand) -> Result<()> {
+    let cache = cache::Cache::from_env()
+        .ok_or_else(|| anyhow::anyhow!("Cannot locate the cache directory; set CARDO_HOME"))?;
+
+    match command {
+        CacheCommand::List => {
+            let entries = cache.list().context("Failed to read cache")?;
+            if entries.is_empty() {
+                println!("Cache is empty ({})", cache.root().display());
+                return Ok(());
+            }
+
+            println!("Cache ({}):", cache.root().display());
+            let mut total = 0;
+            for entry in &entries {
+                total += entry.size;
+                let source = entry.urls.first().map(String::as_str).unwrap_or("-");
+                println!("  {}  {:>8}  {}", &entry.sha256[..12], entry.size, source);
+            }
+            println!("{} files, {} bytes", entries.len(), total);
+        }
+        CacheCommand::Gc { days } => {
+            let max_age = std::time::Duration::from_secs(days.saturating_mul(24 * 60 * 60));
+            let (removed, freed) = cache.gc(max_age).context("Failed to clean cache")?;
+            println!("Removed {} files, freed {} bytes", removed, freed);
+        }
+        CacheCommand::Clear => {
+            cache.clear().context("Failed to clear cache")?;
+            println!("Cleared {}", cache.root().display());
+        }
+    }
+
     Ok(())
 }
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const MANAGED_FILE_NAME: &str = ".cardo-manifest";
+const MANAGED_FILE_HEADER: &str = "# Files written by cardo, relative to this directory.\n\
+# `cardo clean` removes only these.\n";
+
+/// The files and symlinks cardo wrote into an output directory, recorded in
+/// `<out-dir>/.cardo-manifest` so that files added by hand are never deleted.
+#[derive(Debug, Default)]
+pub struct ManagedFiles {
+    /// Path -> dependency that wrote it, if known.
+    paths: BTreeMap<String, Option<String>>,
+}
+
+impl ManagedFiles {
+    fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(MANAGED_FILE_NAME)
+    }
+
+    /// Loads the record, or `None` if cardo has not written one into `output_dir` yet.
+    pub fn load(output_dir: &Path) -> io::Result<Option<Self>> {
+        let content = match fs::read_to_string(Self::path(output_dir)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // 每行为 `路径<TAB>依赖名`
+        let paths = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match line.split_once('\t') {
+                Some((path, owner)) => (path.to_string(), Some(owner.to_string())),
+                None => (line.to_string(), None),
+            })
+            .collect();
+        Ok(Some(Self { paths }))
+    }
+
+    /// Writes the record, deleting it when nothing is managed any more.
+    pub fn save(&self, output_dir: &Path) -> io::Result<()> {
+        let path = Self::path(output_dir);
+        if self.paths.is_empty() {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        let mut content = MANAGED_FILE_HEADER.to_string();
+        for (managed, owner) in &self.paths {
+            content.push_str(managed);
+            if let Some(owner) = owner {
+                content.push('\t');
+                content.push_str(owner);
+            }
+            content.push('\n');
+        }
+        crate::utils::write_atomic(&path, content.as_bytes())
+    }
+
+    pub fn insert(&mut self, path: &str, owner: &str) {
+        self.paths.insert(path.to_string(), Some(owner.to_string()));
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        self.paths.remove(path);
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.paths.contains_key(path)
+    }
+
+    /// Whether `path` is managed itself or lies under a managed symlinked directory.
+    pub fn covers(&self, path: &str) -> bool {
+        self.contains(path)
+            || path
+                .match_indices('/')
+                .any(|(i, _)| self.paths.contains_key(&path[..i]))
+    }
+
+    /// Managed paths with the dependency that wrote each one.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.paths
+            .iter()
+            .map(|(path, owner)| (path.as_str(), owner.as_deref()))
+    }
+}
+
+impl FromIterator<String> for ManagedFiles {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+        Self {
+            paths: iter.into_iter().map(|path| (path, None)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("cardo-managed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(ManagedFiles::load(&dir).unwrap().is_none());
+
+        let mut managed: ManagedFiles =
+            ["o-r/docs/a.md".to_string(), "local".to_string()].into_iter().collect();
+        managed.insert("o-r/b.md", "b");
+        managed.save(&dir).unwrap();
+
+        let loaded = ManagedFiles::load(&dir).unwrap().unwrap();
+        assert_eq!(
+            loaded.iter().collect::<Vec<_>>(),
+            vec![("local", None), ("o-r/b.md", Some("b")), ("o-r/docs/a.md", None)]
+        );
+        assert!(loaded.covers("local/notes/x.md"));
+        assert!(!loaded.covers("o-r/docs/notes.md"));
+
+        let mut loaded = loaded;
+        for path in ["local", "o-r/b.md", "o-r/docs/a.md"] {
+            loaded.remove(path);
+        }
+        loaded.save(&dir).unwrap();
+        assert!(!dir.join(MANAGED_FILE_NAME).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
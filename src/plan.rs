@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Every destination a fetch is about to write, checked before anything touches the disk.
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// Claimed output path -> dependency writing it.
+    claims: HashMap<String, String>,
+    /// Directories that have to exist for the claimed paths -> first dependency needing them.
+    dirs: HashMap<String, String>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PlanError {
+    #[error("`{name}` would write outside the output directory: {path:?}")]
+    UnsafePath { name: String, path: String },
+    #[error("`{first}` and `{second}` both write to {path}")]
+    Collision {
+        path: String,
+        first: String,
+        second: String,
+    },
+}
+
+impl Plan {
+    /// Records that `name` writes `path`, a file or a symlinked directory.
+    pub fn claim(&mut self, name: &str, path: &str) -> Result<(), PlanError> {
+        if !is_safe(path) {
+            return Err(PlanError::UnsafePath {
+                name: name.to_string(),
+                path: path.to_string(),
+            });
+        }
+
+        let collision = |path: &str, first: &str| PlanError::Collision {
+            path: path.to_string(),
+            first: first.to_string(),
+            second: name.to_string(),
+        };
+        // 同一路径、或一个依赖的文件位于另一个依赖的文件“之下”，都会互相覆盖
+        if let Some(first) = self.claims.get(path).or_else(|| self.dirs.get(path)) {
+            return Err(collision(path, first));
+        }
+        let ancestors: Vec<&str> = path.match_indices('/').map(|(i, _)| &path[..i]).collect();
+        if let Some((dir, first)) = ancestors
+            .iter()
+            .find_map(|dir| self.claims.get(*dir).map(|first| (dir, first)))
+        {
+            return Err(collision(dir, first));
+        }
+
+        for dir in ancestors {
+            self.dirs
+                .entry(dir.to_string())
+                .or_insert_with(|| name.to_string());
+        }
+        self.claims.insert(path.to_string(), name.to_string());
+        Ok(())
+    }
+}
+
+/// Whether `path` stays inside the directory it is joined to: relative, without `..`,
+/// and without characters Windows resolves elsewhere or does not allow in file names.
+pub fn is_safe(path: &str) -> bool {
+    // 在所有平台上都拒绝，同一份清单在 Windows 上也能写入；`:` 在 NTFS 上指向备用数据流
+    !path.is_empty()
+        && !path.starts_with('/')
+        && !path.contains(['\\', ':', '*', '?', '"', '<', '>', '|'])
+        && !path.chars().any(char::is_control)
+        && path.split('/').all(|segment| !matches!(segment, "" | "." | ".."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsafe_paths() {
+        assert!(is_safe("owner-repo/docs/api.md"));
+        assert!(!is_safe("../../etc/passwd"));
+        assert!(!is_safe("docs/../../x.md"));
+        assert!(!is_safe("/etc/passwd"));
+        assert!(!is_safe("docs\\..\\x.md"));
+        assert!(!is_safe("C:/x.md"));
+        assert!(!is_safe("c:x.md"));
+        assert!(!is_safe("notes/10:30 meeting.md"));
+        assert!(!is_safe("faq/why?.md"));
+        assert!(!is_safe("a|b.md"));
+        assert!(is_safe("c#/intro.md"));
+        assert!(!is_safe(""));
+    }
+
+    #[test]
+    fn test_collisions() {
+        let mut plan = Plan::default();
+        plan.claim("a", "README.md").unwrap();
+        plan.claim("b", "docs/api.md").unwrap();
+        plan.claim("b", "docs/guide.md").unwrap();
+
+        assert_eq!(
+            plan.claim("c", "README.md"),
+            Err(PlanError::Collision {
+                path: "README.md".to_string(),
+                first: "a".to_string(),
+                second: "c".to_string(),
+            })
+        );
+        // 文件与目录互相冲突
+        assert!(plan.claim("d", "docs").is_err());
+        assert!(plan.claim("e", "README.md/x.md").is_err());
+        assert!(matches!(
+            plan.claim("f", "../x.md"),
+            Err(PlanError::UnsafePath { .. })
+        ));
+        plan.claim("g", "docs/other.md").unwrap();
+    }
+}
@@ -1,4 +1,10 @@
-use std::path::Path;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 pub fn find_config_file() -> Option<String> {
     let current_dir = std::env::current_dir().ok()?;
@@ -25,6 +31,139 @@ pub fn ensure_output_dir(dir: &str) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Replaces `path` with `content` by writing a temporary file next to it and renaming it,
+/// so readers never see a half-written file.
+pub fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension(format!(
+        "tmp-{}-{}",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&temp, content)?;
+    std::fs::rename(&temp, path)
+}
+
+/// Directory containing the manifest; relative paths in markdown.toml are resolved against it.
+pub fn project_dir(config_file: &str) -> PathBuf {
+    match Path::new(config_file).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Output directory `out_dir` from the manifest, resolved against the manifest's directory.
+pub fn output_dir(config_file: &str, out_dir: &str) -> String {
+    let project_dir = project_dir(config_file);
+    if project_dir == Path::new(".") {
+        return out_dir.to_string();
+    }
+    project_dir.join(out_dir).display().to_string()
+}
+
+/// Recursively lists files under `dir`, skipping hidden entries, in sorted order.
+/// Symlinks to files are listed; symlinked directories are not entered.
+pub fn list_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            // 不跟随符号链接进入目录，以免列出目录之外的文件或陷入循环
+            let file_type = std::fs::symlink_metadata(&path)?.file_type();
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() || (file_type.is_symlink() && path.is_file()) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(unix)]
+pub fn symlink(target: &Path, link: &Path, _is_dir: bool) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+pub fn symlink(target: &Path, link: &Path, is_dir: bool) -> std::io::Result<()> {
+    if is_dir {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+pub fn is_full_sha(git_ref: &str) -> bool {
+    git_ref.len() == 40 && git_ref.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Converts a hex SHA-256 digest to a Subresource Integrity value (`sha256-<base64>`).
+pub fn sri_from_hex(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some(format!("sha256-{}", BASE64.encode(bytes)))
+}
+
+/// Converts a `sha256-<base64>` Subresource Integrity value to a hex digest.
+pub fn hex_from_sri(sri: &str) -> Option<String> {
+    let bytes = BASE64.decode(sri.strip_prefix("sha256-")?).ok()?;
+    if bytes.len() != 32 {
+        return None;
+    }
+    Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Percent-encodes everything except unreserved URL characters, including `/`.
+pub fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Percent-encodes each segment of a `/`-separated path, keeping the separators.
+pub fn percent_encode_path(path: &str) -> String {
+    path.split('/').map(percent_encode).collect::<Vec<_>>().join("/")
+}
+
+/// Decodes `%XX` escapes; `None` if an escape is malformed or the result is not UTF-8.
+pub fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -34,4 +173,55 @@ mod tests {
         // 这个测试需要在实际环境中运行
         let _ = find_config_file();
     }
+
+    #[test]
+    fn test_project_dir() {
+        assert_eq!(project_dir("markdown.toml"), PathBuf::from("."));
+        assert_eq!(project_dir("../markdown.toml"), PathBuf::from(".."));
+    }
+
+    #[test]
+    fn test_list_files() {
+        let dir = std::env::temp_dir().join(format!("cardo-list-{}", std::process::id()));
+        let outside = dir.join(".outside");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(dir.join("a.md"), "a").unwrap();
+        std::fs::write(dir.join("sub/b.md"), "b").unwrap();
+        std::fs::write(outside.join("x.md"), "x").unwrap();
+        symlink(&dir.join("a.md"), &dir.join("c.md"), false).unwrap();
+        symlink(&outside, &dir.join("linked"), true).unwrap();
+        symlink(&dir, &dir.join("sub/loop"), true).unwrap();
+
+        assert_eq!(
+            list_files(&dir).unwrap(),
+            vec![dir.join("a.md"), dir.join("c.md"), dir.join("sub/b.md")]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_sri_roundtrip() {
+        let hex = sha256_hex(b"abc");
+        let sri = sri_from_hex(&hex).unwrap();
+        assert_eq!(sri, "sha256-ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=");
+        assert_eq!(hex_from_sri(&sri).unwrap(), hex);
+        assert!(hex_from_sri("sha512-abc").is_none());
+    }
+
+    #[test]
+    fn test_percent_encoding() {
+        let encoded = percent_encode("docs/über api.md");
+        assert_eq!(encoded, "docs%2F%C3%BCber%20api.md");
+        assert_eq!(percent_decode(&encoded).unwrap(), "docs/über api.md");
+        assert!(percent_decode("%2").is_none());
+    }
 }